@@ -1,19 +1,35 @@
 use crate::MergeError;
 use std::fmt::Debug;
 use std::path::Path;
+use std::sync::Arc;
 
 pub mod common;
 pub mod mac;
+pub mod thin;
+pub mod writer;
+
+/// The contents of an object that was only extracted in memory
+pub type ObjectData = Arc<[u8]>;
 
 pub trait ArBuilder: Debug {
     fn append_obj(&mut self, path: &Path) -> Result<(), MergeError>;
+    /// Appends an object extracted from an input library under its original member `name`,
+    /// along with the file it was copied from when it is the member of a thin archive.
+    /// `data` is the contents of the object when it was only extracted in memory, and not to `path`.
+    fn append_extracted_obj(
+        &mut self,
+        path: &Path,
+        name: &str,
+        origin: Option<&Path>,
+        data: Option<ObjectData>,
+    ) -> Result<(), MergeError>;
     fn close(self: Box<Self>) -> Result<(), MergeError>;
 }
 
-pub fn host_platform_builder(path: &Path) -> Box<dyn ArBuilder> {
+pub fn host_platform_builder(path: &Path, deterministic: bool) -> Box<dyn ArBuilder> {
     if std::env::consts::OS == "macos" {
-        Box::new(mac::MacArBuilder::new(path))
+        Box::new(mac::MacArBuilder::new(path, deterministic))
     } else {
-        Box::new(common::CommonArBuilder::new(path))
+        Box::new(common::CommonArBuilder::new(path, deterministic))
     }
 }
@@ -1,54 +1,51 @@
-use crate::arbuilder::ArBuilder;
-use crate::{archives, MergeError};
-use ar::Builder;
-use std::fmt::{Debug, Formatter};
-use std::fs::File;
+use crate::arbuilder::writer::ArchiveWriter;
+use crate::arbuilder::{ArBuilder, ObjectData};
+use crate::MergeError;
+use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 
+#[derive(Debug)]
 pub struct CommonArBuilder {
-    builder: Builder<File>,
-    output_path: PathBuf,
+    writer: ArchiveWriter,
     closed: bool,
 }
 
-impl Debug for CommonArBuilder {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("CommonArBuilder")
-            .field("output_path", &self.output_path)
-            .field("closed", &self.closed)
-            .finish()
-    }
-}
-
 impl ArBuilder for CommonArBuilder {
     fn append_obj(&mut self, path: &Path) -> Result<(), MergeError> {
-        self.builder
-            .append_path(path)
-            .map_err(MergeError::WritingArchive)?;
+        self.writer.append_path(path);
+        Ok(())
+    }
+
+    fn append_extracted_obj(
+        &mut self,
+        path: &Path,
+        name: &str,
+        _origin: Option<&Path>,
+        data: Option<ObjectData>,
+    ) -> Result<(), MergeError> {
+        self.writer.append_named(path, name, data);
         Ok(())
     }
 
     fn close(mut self: Box<Self>) -> Result<(), MergeError> {
-        self.finalize_index()
+        self.write_archive()
     }
 }
 
 impl CommonArBuilder {
-    pub fn new(path: impl Into<PathBuf>) -> Self {
-        let path = path.into();
+    pub fn new(path: impl Into<PathBuf>, deterministic: bool) -> Self {
         Self {
-            builder: Builder::new(File::create(&path).expect("Failed to create output library")),
-            output_path: path,
+            writer: ArchiveWriter::new(path, None).with_deterministic(deterministic),
             closed: false,
         }
     }
 
-    fn finalize_index(&mut self) -> Result<(), MergeError> {
+    fn write_archive(&mut self) -> Result<(), MergeError> {
         if self.closed {
             return Ok(());
         }
         self.closed = true;
 
-        archives::create_index(&self.output_path)
+        self.writer.write()
     }
 }
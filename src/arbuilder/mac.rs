@@ -1,21 +1,28 @@
-use crate::arbuilder::ArBuilder;
+use crate::arbuilder::writer::{ArFormat, ArchiveWriter};
+use crate::arbuilder::{ArBuilder, ObjectData};
 use crate::MergeError;
-use crate::MergeError::ExternalToolLaunchError;
-use std::ffi::OsString;
-use std::path::{Path, PathBuf};
-use std::process::Command;
-use tracing::info;
+use std::path::Path;
 
 #[derive(Debug)]
 pub struct MacArBuilder {
-    output_path: PathBuf,
-    obj_paths: Vec<PathBuf>,
+    writer: ArchiveWriter,
     closed: bool,
 }
 
 impl ArBuilder for MacArBuilder {
     fn append_obj(&mut self, path: &Path) -> Result<(), MergeError> {
-        self.obj_paths.push(path.to_owned());
+        self.writer.append_path(path);
+        Ok(())
+    }
+
+    fn append_extracted_obj(
+        &mut self,
+        path: &Path,
+        name: &str,
+        _origin: Option<&Path>,
+        data: Option<ObjectData>,
+    ) -> Result<(), MergeError> {
+        self.writer.append_named(path, name, data);
         Ok(())
     }
 
@@ -25,10 +32,10 @@ impl ArBuilder for MacArBuilder {
 }
 
 impl MacArBuilder {
-    pub fn new(path: &Path) -> Self {
+    pub fn new(path: &Path, deterministic: bool) -> Self {
         Self {
-            output_path: path.to_owned(),
-            obj_paths: vec![],
+            writer: ArchiveWriter::new(path, Some(ArFormat::Darwin))
+                .with_deterministic(deterministic),
             closed: false,
         }
     }
@@ -39,47 +46,6 @@ impl MacArBuilder {
         }
         self.closed = true;
 
-        let mut args = [
-            OsString::from("-static"),
-            OsString::from("-o"),
-            self.output_path.as_os_str().to_owned(),
-        ]
-        .to_vec();
-        let mut count = 0;
-        args.extend(
-            self.obj_paths
-                .iter()
-                .inspect(|_| count += 1)
-                .map(|p| p.as_os_str().into()),
-        );
-
-        info!(
-            "Merging {} objects: libtool {}",
-            count,
-            args.iter()
-                .map(|s| s.to_string_lossy())
-                .collect::<Vec<_>>()
-                .join(" ")
-        );
-
-        let output =
-            Command::new("libtool")
-                .args(&args)
-                .output()
-                .map_err(|e| ExternalToolLaunchError {
-                    tool: "libtool".to_string(),
-                    inner: e,
-                })?;
-        if output.status.success() {
-            Ok(())
-        } else {
-            Err(MergeError::ExternalToolError {
-                reason: "Failed to merge object files with `libtool`".to_string(),
-                tool: "libtool".to_string(),
-                args,
-                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-            })
-        }
+        self.writer.write()
     }
 }
@@ -0,0 +1,153 @@
+use crate::arbuilder::writer::{ArFormat, ArchiveWriter, MemberNames};
+use crate::arbuilder::{ArBuilder, ObjectData};
+use crate::MergeError;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Lists the objects armerge copied to the objects dir, so they can be removed when the archive is rewritten
+const MANIFEST_NAME: &str = ".armerge-objects";
+
+/// Writes a GNU thin archive, which references the objects in place instead of copying them.
+///
+/// Objects that don't outlive the merge (the ones extracted from regular archives, or the merged
+/// object) are copied to a `<output>.objects` directory next to the archive. The objects copied by
+/// a previous run are replaced every time the archive is written, but a directory that armerge
+/// didn't create is never touched.
+#[derive(Debug)]
+pub struct ThinArBuilder {
+    writer: ArchiveWriter,
+    objects_dir: PathBuf,
+    /// The objects to copy (or their contents, when they are only in memory), and where to copy them in the objects dir
+    copied_objects: Vec<(PathBuf, Option<ObjectData>, PathBuf)>,
+    copied_names: MemberNames,
+    closed: bool,
+}
+
+impl ArBuilder for ThinArBuilder {
+    fn append_obj(&mut self, path: &Path) -> Result<(), MergeError> {
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        self.append_copied_obj(path, &name, None);
+        Ok(())
+    }
+
+    fn append_extracted_obj(
+        &mut self,
+        path: &Path,
+        name: &str,
+        origin: Option<&Path>,
+        data: Option<ObjectData>,
+    ) -> Result<(), MergeError> {
+        match origin {
+            Some(origin) => self.writer.append_path(origin),
+            None => self.append_copied_obj(path, name, data),
+        }
+        Ok(())
+    }
+
+    fn close(mut self: Box<Self>) -> Result<(), MergeError> {
+        self.write_archive()
+    }
+}
+
+impl ThinArBuilder {
+    pub fn new(path: impl Into<PathBuf>, deterministic: bool) -> Self {
+        let path = path.into();
+        let mut objects_dir = path.clone().into_os_string();
+        objects_dir.push(".objects");
+        Self {
+            writer: ArchiveWriter::new(path, Some(ArFormat::GnuThin))
+                .with_deterministic(deterministic),
+            objects_dir: objects_dir.into(),
+            copied_objects: Vec::new(),
+            copied_names: MemberNames::default(),
+            closed: false,
+        }
+    }
+
+    fn append_copied_obj(&mut self, path: &Path, name: &str, data: Option<ObjectData>) {
+        let copied_path = self.objects_dir.join(self.copied_names.unique(name));
+        self.writer.append_path(&copied_path);
+        self.copied_objects
+            .push((path.to_owned(), data, copied_path));
+    }
+
+    fn write_archive(&mut self) -> Result<(), MergeError> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+
+        self.remove_previous_objects()
+            .map_err(MergeError::WritingArchive)?;
+        if !self.copied_objects.is_empty() {
+            info!(
+                "Copying {} objects to {}",
+                self.copied_objects.len(),
+                self.objects_dir.display()
+            );
+            std::fs::create_dir_all(&self.objects_dir).map_err(MergeError::WritingArchive)?;
+            let mut manifest = String::new();
+            for (path, data, copied_path) in &self.copied_objects {
+                if copied_path.exists() {
+                    return Err(MergeError::WritingArchive(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} was not written by armerge", copied_path.display()),
+                    )));
+                }
+                match data {
+                    Some(data) => std::fs::write(copied_path, data),
+                    None => std::fs::copy(path, copied_path).map(|_| ()),
+                }
+                .map_err(MergeError::WritingArchive)?;
+                if let Some(name) = copied_path.file_name() {
+                    manifest.push_str(&name.to_string_lossy());
+                    manifest.push('\n');
+                }
+            }
+            std::fs::write(self.objects_dir.join(MANIFEST_NAME), manifest)
+                .map_err(MergeError::WritingArchive)?;
+        }
+
+        self.writer.write()
+    }
+
+    /// Removes the objects listed in the manifest of a previous run, and the objects dir if nothing else is left in it
+    fn remove_previous_objects(&self) -> io::Result<()> {
+        if !self.objects_dir.exists() {
+            return Ok(());
+        }
+        let manifest_path = self.objects_dir.join(MANIFEST_NAME);
+        let manifest = match std::fs::read_to_string(&manifest_path) {
+            Ok(manifest) => manifest,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "{} exists but was not created by armerge",
+                        self.objects_dir.display()
+                    ),
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        for name in manifest.lines().filter(|name| !name.is_empty()) {
+            // Only plain file names are written to the manifest
+            if Path::new(name).file_name() != Some(name.as_ref()) {
+                continue;
+            }
+            match std::fs::remove_file(self.objects_dir.join(name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        std::fs::remove_file(manifest_path)?;
+        if std::fs::read_dir(&self.objects_dir)?.next().is_none() {
+            std::fs::remove_dir(&self.objects_dir)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,783 @@
+use crate::arbuilder::ObjectData;
+use crate::MergeError;
+use goblin::{peek_bytes, Hint};
+use object::read::coff::ImportFile;
+use object::{BinaryFormat, FileKind, Object, ObjectSymbol, SymbolKind};
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+const THIN_AR_MAGIC: &[u8] = b"!<thin>\n";
+const HEADER_SIZE: u64 = 60;
+const GNU_SYMTAB_NAME: &[u8] = b"/";
+const GNU_SYMTAB64_NAME: &[u8] = b"/SYM64/";
+const GNU_LONG_NAMES_NAME: &[u8] = b"//";
+const DARWIN_SYMDEF_NAME: &[u8] = b"__.SYMDEF SORTED";
+const DARWIN_SYMDEF64_NAME: &[u8] = b"__.SYMDEF_64 SORTED";
+const COFF_LINKER_MEMBER_NAME: &[u8] = b"/";
+const COFF_LONG_NAMES_NAME: &[u8] = b"//";
+
+/// The flavor of `ar` archive to write
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ArFormat {
+    /// GNU/SysV archive, with a `/` (or `/SYM64/`) symbol table and a `//` long names table
+    Gnu,
+    /// GNU thin archive, whose members are only references to object files outside the archive.
+    /// Member names are paths relative to the archive's directory.
+    GnuThin,
+    /// BSD archive as written by Apple's toolchain, with a `__.SYMDEF SORTED` symbol table,
+    /// `#1/NN` member names and 8-byte aligned members
+    Darwin,
+    /// MSVC `.lib` archive, with a first (big-endian) and a second (little-endian, sorted)
+    /// linker member followed by a `//` long names table
+    Coff,
+}
+
+/// A member of the archive, with everything we need to know to lay it out before writing it
+struct MemberInfo {
+    path: PathBuf,
+    /// The contents of the member, when it isn't read from `path`
+    data: Option<ObjectData>,
+    name: Vec<u8>,
+    size: u64,
+    mtime: u64,
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    symbols: Vec<Vec<u8>>,
+    is_macho: bool,
+    is_coff: bool,
+}
+
+/// Picks the names of archive members, renaming the ones already taken by an earlier member
+#[derive(Debug, Default)]
+pub struct MemberNames {
+    taken: HashSet<String>,
+}
+
+impl MemberNames {
+    /// The first `foo.o` keeps its name, the next ones are renamed `foo.1.o`, `foo.2.o`, etc.
+    pub fn unique(&mut self, name: &str) -> String {
+        if self.taken.insert(name.to_owned()) {
+            return name.to_owned();
+        }
+        let (stem, extension) = match name.rfind('.') {
+            Some(pos) if pos > 0 => name.split_at(pos),
+            _ => (name, ""),
+        };
+        let mut counter = 1;
+        loop {
+            let renamed = format!("{stem}.{counter}{extension}");
+            if self.taken.insert(renamed.clone()) {
+                return renamed;
+            }
+            counter += 1;
+        }
+    }
+}
+
+/// Writes an `ar` archive along with its symbol index, without relying on an external `ranlib`
+#[derive(Debug)]
+pub struct ArchiveWriter {
+    output_path: PathBuf,
+    format: Option<ArFormat>,
+    /// The files to add to the archive, their member name, and their contents if they are only in memory
+    members: Vec<(PathBuf, String, Option<ObjectData>)>,
+    names: MemberNames,
+    deterministic: bool,
+}
+
+impl ArchiveWriter {
+    /// If `format` is `None`, it is picked based on the type of the objects in the archive
+    pub fn new(output_path: impl Into<PathBuf>, format: Option<ArFormat>) -> Self {
+        Self {
+            output_path: output_path.into(),
+            format,
+            members: Vec::new(),
+            names: MemberNames::default(),
+            deterministic: false,
+        }
+    }
+
+    /// Like `ar D`, write zero timestamps, uids and gids, and the same mode for every member,
+    /// so that the archive only depends on the contents of its members
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn append_path(&mut self, path: &Path) {
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        self.append_named(path, &name, None);
+    }
+
+    /// Adds a file to the archive under a different member name,
+    /// which is renamed if an earlier member already has the same name.
+    /// When `data` is set, it is written instead of the contents of the file, which may not exist.
+    pub fn append_named(&mut self, path: &Path, name: &str, data: Option<ObjectData>) {
+        let name = self.names.unique(name);
+        self.members.push((path.to_owned(), name, data));
+    }
+
+    pub fn write(&self) -> Result<(), MergeError> {
+        let members = self
+            .members
+            .par_iter()
+            .map(|(path, name, data)| read_member_info(path, name, data, self.deterministic))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let format = self.format.unwrap_or_else(|| {
+            if members.iter().any(|m| m.is_macho) {
+                ArFormat::Darwin
+            } else if members.iter().any(|m| m.is_coff) {
+                ArFormat::Coff
+            } else {
+                ArFormat::Gnu
+            }
+        });
+        let symbol_count = members.iter().map(|m| m.symbols.len()).sum::<usize>();
+        info!(
+            "Writing archive index with {} symbols for {} members to {}",
+            symbol_count,
+            members.len(),
+            self.output_path.display()
+        );
+
+        let file = File::create(&self.output_path).map_err(MergeError::WritingArchive)?;
+        let mut out = BufWriter::new(file);
+        match format {
+            ArFormat::Gnu => write_gnu_archive(&mut out, &members),
+            ArFormat::GnuThin => write_gnu_thin_archive(&mut out, &self.output_path, &members),
+            ArFormat::Darwin => write_darwin_archive(&mut out, &members),
+            ArFormat::Coff => write_coff_archive(&mut out, &members),
+        }
+        .and_then(|_| out.flush())
+        .map_err(MergeError::WritingArchive)
+    }
+}
+
+fn read_member_info(
+    path: &Path,
+    name: &str,
+    in_memory: &Option<ObjectData>,
+    deterministic: bool,
+) -> Result<MemberInfo, MergeError> {
+    let data = match in_memory {
+        Some(data) => Cow::Borrowed(&data[..]),
+        None => Cow::Owned(std::fs::read(path)?),
+    };
+
+    let (mtime, uid, gid, mode) = if deterministic {
+        (0, 0, 0, 0o644)
+    } else if in_memory.is_some() {
+        // Like a file that was just extracted
+        (now_timestamp(), 0, 0, 0o644)
+    } else {
+        file_metadata(&std::fs::metadata(path)?)
+    };
+    let is_macho = data.len() >= 16
+        && matches!(
+            peek_bytes(data[..16].try_into().unwrap()),
+            Ok(Hint::Mach(_) | Hint::MachFat(_))
+        );
+    let is_coff = matches!(
+        FileKind::parse(&*data),
+        Ok(FileKind::Coff | FileKind::CoffBig | FileKind::CoffImport)
+    );
+
+    Ok(MemberInfo {
+        path: path.to_owned(),
+        data: in_memory.clone(),
+        name: name.as_bytes().to_vec(),
+        size: data.len() as u64,
+        mtime,
+        uid,
+        gid,
+        mode,
+        symbols: exported_symbols(&data),
+        is_macho,
+        is_coff,
+    })
+}
+
+fn now_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(unix)]
+fn file_metadata(metadata: &std::fs::Metadata) -> (u64, u32, u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+    (
+        metadata.mtime().max(0) as u64,
+        metadata.uid(),
+        metadata.gid(),
+        metadata.mode(),
+    )
+}
+
+#[cfg(not(unix))]
+fn file_metadata(metadata: &std::fs::Metadata) -> (u64, u32, u32, u32) {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    (mtime, 0, 0, 0o644)
+}
+
+/// The defined global symbols of an object, which are the ones a linker looks up in the index.
+/// Members that are not objects we can parse simply don't contribute any symbols.
+fn exported_symbols(data: &[u8]) -> Vec<Vec<u8>> {
+    let Ok(file) = object::File::parse(data) else {
+        return import_member_symbols(data);
+    };
+    // COFF weak externals look undefined, but the MSVC tools still index them
+    let is_coff = file.format() == BinaryFormat::Coff;
+    file.symbols()
+        .filter(|sym| {
+            sym.is_global()
+                && (!sym.is_undefined() || (is_coff && sym.is_weak()))
+                && sym.kind() != SymbolKind::Section
+                && sym.kind() != SymbolKind::File
+        })
+        .filter_map(|sym| sym.name_bytes().ok())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_vec())
+        .collect()
+}
+
+/// Short import members of import libraries define the `__imp_` pointer, and a thunk for code
+fn import_member_symbols(data: &[u8]) -> Vec<Vec<u8>> {
+    let Ok(import) = ImportFile::parse(data) else {
+        return Vec::new();
+    };
+    let mut symbols = vec![[b"__imp_", import.symbol()].concat()];
+    if matches!(import.import_type(), object::read::coff::ImportType::Code) {
+        symbols.push(import.symbol().to_vec());
+    }
+    symbols
+}
+
+fn write_header(
+    out: &mut impl Write,
+    name: &[u8],
+    mtime: u64,
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    size: u64,
+) -> std::io::Result<()> {
+    // Like llvm-ar, metadata that doesn't fit its field is written as 0, but a size that doesn't fit can't be stored
+    let fit = |value: String, width: usize| {
+        if value.len() <= width {
+            value
+        } else {
+            "0".to_string()
+        }
+    };
+    let size = size.to_string();
+    if size.len() > 10 || name.len() > 16 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "the name or size ({size} bytes) of member {} doesn't fit in an archive header",
+                String::from_utf8_lossy(name)
+            ),
+        ));
+    }
+
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(name);
+    header.resize(16, b' ');
+    header.extend_from_slice(
+        format!(
+            "{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            fit(mtime.to_string(), 12),
+            fit(uid.to_string(), 6),
+            fit(gid.to_string(), 6),
+            fit(format!("{mode:o}"), 8),
+            size
+        )
+        .as_bytes(),
+    );
+    out.write_all(&header)
+}
+
+fn write_special_header(out: &mut impl Write, name: &[u8], size: u64) -> std::io::Result<()> {
+    write_header(out, name, 0, 0, 0, 0, size)
+}
+
+fn write_member_data(out: &mut impl Write, member: &MemberInfo) -> std::io::Result<()> {
+    if let Some(data) = &member.data {
+        return out.write_all(data);
+    }
+    let mut file = File::open(&member.path)?;
+    let copied = std::io::copy(&mut (&mut file).take(member.size), out)?;
+    if copied != member.size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!(
+                "{} changed size while writing archive",
+                member.path.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn pad_to_even(out: &mut impl Write, size: u64) -> std::io::Result<()> {
+    if size % 2 != 0 {
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn padded(size: u64) -> u64 {
+    size + size % 2
+}
+
+/// GNU member names are terminated by a `/`, names that don't fit go to the `//` member
+fn gnu_member_names(members: &[MemberInfo]) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let mut long_names = Vec::new();
+    let names = members
+        .iter()
+        .map(|m| {
+            if m.name.len() < 16 {
+                let mut name = m.name.clone();
+                name.push(b'/');
+                name
+            } else {
+                let offset = long_names.len();
+                long_names.extend_from_slice(&m.name);
+                long_names.extend_from_slice(b"/\n");
+                format!("/{offset}").into_bytes()
+            }
+        })
+        .collect();
+    (names, long_names)
+}
+
+fn gnu_member_offsets(members: &[MemberInfo], symtab_size: u64, long_names_size: u64) -> Vec<u64> {
+    let mut pos = AR_MAGIC.len() as u64 + HEADER_SIZE + padded(symtab_size);
+    if long_names_size != 0 {
+        pos += HEADER_SIZE + padded(long_names_size);
+    }
+    members
+        .iter()
+        .map(|m| {
+            let offset = pos;
+            pos += HEADER_SIZE + padded(m.size);
+            offset
+        })
+        .collect()
+}
+
+fn write_gnu_archive(out: &mut impl Write, members: &[MemberInfo]) -> std::io::Result<()> {
+    let (names, long_names) = gnu_member_names(members);
+    let symbol_count = members.iter().map(|m| m.symbols.len() as u64).sum::<u64>();
+    let strings_size = members
+        .iter()
+        .flat_map(|m| m.symbols.iter())
+        .map(|s| s.len() as u64 + 1)
+        .sum::<u64>();
+
+    // The index has to be switched to 64bit offsets if any member starts beyond 4GiB
+    let mut word_size = 4;
+    let mut symtab_size = word_size * (1 + symbol_count) + strings_size;
+    let mut offsets = gnu_member_offsets(members, symtab_size, long_names.len() as u64);
+    if offsets.last().is_some_and(|&o| o > u32::MAX as u64) {
+        word_size = 8;
+        symtab_size = word_size * (1 + symbol_count) + strings_size;
+        offsets = gnu_member_offsets(members, symtab_size, long_names.len() as u64);
+    }
+
+    out.write_all(AR_MAGIC)?;
+    let symtab_name = if word_size == 8 {
+        GNU_SYMTAB64_NAME
+    } else {
+        GNU_SYMTAB_NAME
+    };
+    write_special_header(out, symtab_name, symtab_size)?;
+    let write_word = |out: &mut dyn Write, v: u64| {
+        if word_size == 8 {
+            out.write_all(&v.to_be_bytes())
+        } else {
+            out.write_all(&(v as u32).to_be_bytes())
+        }
+    };
+    write_word(out, symbol_count)?;
+    for (member, &offset) in members.iter().zip(&offsets) {
+        for _ in &member.symbols {
+            write_word(out, offset)?;
+        }
+    }
+    for sym in members.iter().flat_map(|m| m.symbols.iter()) {
+        out.write_all(sym)?;
+        out.write_all(b"\0")?;
+    }
+    pad_to_even(out, symtab_size)?;
+
+    if !long_names.is_empty() {
+        write_special_header(out, GNU_LONG_NAMES_NAME, long_names.len() as u64)?;
+        out.write_all(&long_names)?;
+        pad_to_even(out, long_names.len() as u64)?;
+    }
+
+    for (member, name) in members.iter().zip(&names) {
+        write_header(
+            out,
+            name,
+            member.mtime,
+            member.uid,
+            member.gid,
+            member.mode,
+            member.size,
+        )?;
+        write_member_data(out, member)?;
+        pad_to_even(out, member.size)?;
+    }
+    Ok(())
+}
+
+/// The path of a thin archive member, as seen from the directory of the archive
+fn thin_member_name(archive_path: &Path, member_path: &Path) -> std::io::Result<Vec<u8>> {
+    let archive_dir = match archive_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let archive_dir = std::fs::canonicalize(archive_dir)?;
+    let member_path = std::fs::canonicalize(member_path)?;
+    let common = archive_dir
+        .components()
+        .zip(member_path.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    // Paths without any common root (e.g. on different Windows drives) can only be absolute
+    let relative = if common == 0 {
+        member_path
+    } else {
+        let mut relative = PathBuf::new();
+        for _ in archive_dir.components().skip(common) {
+            relative.push("..");
+        }
+        relative.extend(member_path.components().skip(common));
+        relative
+    };
+    Ok(relative.to_string_lossy().replace('\\', "/").into_bytes())
+}
+
+fn write_gnu_thin_archive(
+    out: &mut impl Write,
+    archive_path: &Path,
+    members: &[MemberInfo],
+) -> std::io::Result<()> {
+    // Thin archives always keep their member names in the long names table
+    let mut long_names = Vec::new();
+    let mut names = Vec::with_capacity(members.len());
+    for member in members {
+        names.push(format!("/{}", long_names.len()).into_bytes());
+        long_names.extend_from_slice(&thin_member_name(archive_path, &member.path)?);
+        long_names.extend_from_slice(b"/\n");
+    }
+
+    let symbol_count = members.iter().map(|m| m.symbols.len() as u64).sum::<u64>();
+    let strings_size = members
+        .iter()
+        .flat_map(|m| m.symbols.iter())
+        .map(|s| s.len() as u64 + 1)
+        .sum::<u64>();
+    let symtab_size = 4 * (1 + symbol_count) + strings_size;
+
+    // Only the member headers are in the archive, so the offsets can't go beyond 4GiB
+    let mut pos = THIN_AR_MAGIC.len() as u64
+        + HEADER_SIZE
+        + padded(symtab_size)
+        + HEADER_SIZE
+        + padded(long_names.len() as u64);
+    let offsets = members
+        .iter()
+        .map(|_| {
+            pos += HEADER_SIZE;
+            (pos - HEADER_SIZE) as u32
+        })
+        .collect::<Vec<_>>();
+
+    out.write_all(THIN_AR_MAGIC)?;
+    write_special_header(out, GNU_SYMTAB_NAME, symtab_size)?;
+    out.write_all(&(symbol_count as u32).to_be_bytes())?;
+    for (member, offset) in members.iter().zip(&offsets) {
+        for _ in &member.symbols {
+            out.write_all(&offset.to_be_bytes())?;
+        }
+    }
+    for sym in members.iter().flat_map(|m| m.symbols.iter()) {
+        out.write_all(sym)?;
+        out.write_all(b"\0")?;
+    }
+    pad_to_even(out, symtab_size)?;
+
+    write_special_header(out, GNU_LONG_NAMES_NAME, long_names.len() as u64)?;
+    out.write_all(&long_names)?;
+    pad_to_even(out, long_names.len() as u64)?;
+
+    for (member, name) in members.iter().zip(&names) {
+        write_header(
+            out,
+            name,
+            member.mtime,
+            member.uid,
+            member.gid,
+            member.mode,
+            member.size,
+        )?;
+    }
+    Ok(())
+}
+
+/// Darwin always writes names as `#1/NN` right after the header, padded with NULs so that the
+/// member data which follows is 8-byte aligned (the header always starts 8-byte aligned).
+fn darwin_name_field(name: &[u8]) -> (String, u64) {
+    let padded_len = (name.len() as u64 + HEADER_SIZE).next_multiple_of(8) - HEADER_SIZE;
+    (format!("#1/{padded_len}"), padded_len)
+}
+
+/// Darwin pads the member data to 8 bytes, and counts this padding in the member's size
+fn darwin_padded_size(data_size: u64) -> u64 {
+    data_size.next_multiple_of(8)
+}
+
+fn darwin_member_size(name: &[u8], data_size: u64) -> u64 {
+    HEADER_SIZE + darwin_name_field(name).1 + darwin_padded_size(data_size)
+}
+
+fn write_darwin_member_header(
+    out: &mut impl Write,
+    name: &[u8],
+    mtime: u64,
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    data_size: u64,
+) -> std::io::Result<()> {
+    let (header_name, name_len) = darwin_name_field(name);
+    let size = name_len + darwin_padded_size(data_size);
+    write_header(out, header_name.as_bytes(), mtime, uid, gid, mode, size)?;
+    out.write_all(name)?;
+    out.write_all(&vec![0; name_len as usize - name.len()])
+}
+
+fn write_darwin_padding(out: &mut impl Write, data_size: u64) -> std::io::Result<()> {
+    let padding = darwin_padded_size(data_size) - data_size;
+    out.write_all(&vec![b'\n'; padding as usize])
+}
+
+fn darwin_member_offsets(members: &[MemberInfo], symdef_member_size: u64) -> Vec<u64> {
+    let mut pos = AR_MAGIC.len() as u64 + symdef_member_size;
+    members
+        .iter()
+        .map(|m| {
+            let offset = pos;
+            pos += darwin_member_size(&m.name, m.size);
+            offset
+        })
+        .collect()
+}
+
+fn write_darwin_archive(out: &mut impl Write, members: &[MemberInfo]) -> std::io::Result<()> {
+    // The string table is shared by all the ranlib entries, and the entries are sorted by name
+    let mut sorted_syms = members
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, m)| m.symbols.iter().map(move |s| (s.as_slice(), idx)))
+        .collect::<Vec<_>>();
+    sorted_syms.sort();
+    let mut strtab = Vec::new();
+    let mut strx = Vec::with_capacity(sorted_syms.len());
+    for (sym, _) in &sorted_syms {
+        strx.push(strtab.len() as u64);
+        strtab.extend_from_slice(sym);
+        strtab.push(0);
+    }
+    while strtab.len() % 8 != 0 {
+        strtab.push(0);
+    }
+
+    // Like GNU, fall back to 64bit offsets only when we have to
+    let mut symdef_name = DARWIN_SYMDEF_NAME;
+    let mut word_size = 4;
+    let symdef_size =
+        |word_size: u64| word_size * (2 + 2 * sorted_syms.len() as u64) + strtab.len() as u64;
+    let mut offsets = darwin_member_offsets(
+        members,
+        darwin_member_size(symdef_name, symdef_size(word_size)),
+    );
+    if offsets.last().is_some_and(|&o| o > u32::MAX as u64) {
+        symdef_name = DARWIN_SYMDEF64_NAME;
+        word_size = 8;
+        offsets = darwin_member_offsets(
+            members,
+            darwin_member_size(symdef_name, symdef_size(word_size)),
+        );
+    }
+    let write_word = |out: &mut dyn Write, v: u64| {
+        if word_size == 8 {
+            out.write_all(&v.to_le_bytes())
+        } else {
+            out.write_all(&(v as u32).to_le_bytes())
+        }
+    };
+
+    out.write_all(AR_MAGIC)?;
+    let symdef_data_size = symdef_size(word_size);
+    write_darwin_member_header(out, symdef_name, 0, 0, 0, 0o644, symdef_data_size)?;
+    write_word(out, word_size * 2 * sorted_syms.len() as u64)?;
+    for ((_, member_idx), strx) in sorted_syms.iter().zip(strx) {
+        write_word(out, strx)?;
+        write_word(out, offsets[*member_idx])?;
+    }
+    write_word(out, strtab.len() as u64)?;
+    out.write_all(&strtab)?;
+    write_darwin_padding(out, symdef_data_size)?;
+
+    for member in members {
+        write_darwin_member_header(
+            out,
+            &member.name,
+            member.mtime,
+            member.uid,
+            member.gid,
+            member.mode,
+            member.size,
+        )?;
+        write_member_data(out, member)?;
+        write_darwin_padding(out, member.size)?;
+    }
+    Ok(())
+}
+
+/// MSVC member names are terminated by a `/` like GNU, but long names are NUL-terminated
+fn coff_member_names(members: &[MemberInfo]) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let mut long_names = Vec::new();
+    let names = members
+        .iter()
+        .map(|m| {
+            if m.name.len() < 16 {
+                let mut name = m.name.clone();
+                name.push(b'/');
+                name
+            } else {
+                let offset = long_names.len();
+                long_names.extend_from_slice(&m.name);
+                long_names.push(0);
+                format!("/{offset}").into_bytes()
+            }
+        })
+        .collect();
+    (names, long_names)
+}
+
+fn write_coff_archive(out: &mut impl Write, members: &[MemberInfo]) -> std::io::Result<()> {
+    let (names, long_names) = coff_member_names(members);
+    let symbol_count = members.iter().map(|m| m.symbols.len() as u64).sum::<u64>();
+    let strings_size = members
+        .iter()
+        .flat_map(|m| m.symbols.iter())
+        .map(|s| s.len() as u64 + 1)
+        .sum::<u64>();
+
+    // The second linker member refers to members by a 1-based u16 index, and its symbols are sorted
+    let mut sorted_syms = members
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, m)| m.symbols.iter().map(move |s| (s.as_slice(), idx)))
+        .collect::<Vec<_>>();
+    sorted_syms.sort();
+    if members.len() > u16::MAX as usize {
+        return Err(std::io::Error::other(format!(
+            "Too many members for a COFF archive: {}",
+            members.len()
+        )));
+    }
+
+    let first_size = 4 * (1 + symbol_count) + strings_size;
+    let second_size =
+        4 + 4 * members.len() as u64 + 4 + 2 * sorted_syms.len() as u64 + strings_size;
+    let mut pos = AR_MAGIC.len() as u64
+        + HEADER_SIZE
+        + padded(first_size)
+        + HEADER_SIZE
+        + padded(second_size)
+        + HEADER_SIZE
+        + padded(long_names.len() as u64);
+    let offsets = members
+        .iter()
+        .map(|m| {
+            let offset = pos;
+            pos += HEADER_SIZE + padded(m.size);
+            u32::try_from(offset)
+                .map_err(|_| std::io::Error::other("COFF archives can't have members beyond 4GiB"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    out.write_all(AR_MAGIC)?;
+
+    write_special_header(out, COFF_LINKER_MEMBER_NAME, first_size)?;
+    out.write_all(&(symbol_count as u32).to_be_bytes())?;
+    for (member, &offset) in members.iter().zip(&offsets) {
+        for _ in &member.symbols {
+            out.write_all(&offset.to_be_bytes())?;
+        }
+    }
+    for sym in members.iter().flat_map(|m| m.symbols.iter()) {
+        out.write_all(sym)?;
+        out.write_all(b"\0")?;
+    }
+    pad_to_even(out, first_size)?;
+
+    write_special_header(out, COFF_LINKER_MEMBER_NAME, second_size)?;
+    out.write_all(&(members.len() as u32).to_le_bytes())?;
+    for offset in &offsets {
+        out.write_all(&offset.to_le_bytes())?;
+    }
+    out.write_all(&(sorted_syms.len() as u32).to_le_bytes())?;
+    for (_, member_idx) in &sorted_syms {
+        out.write_all(&(*member_idx as u16 + 1).to_le_bytes())?;
+    }
+    for (sym, _) in &sorted_syms {
+        out.write_all(sym)?;
+        out.write_all(b"\0")?;
+    }
+    pad_to_even(out, second_size)?;
+
+    // Unlike GNU, the MSVC tools always write the long names member, even when it is empty
+    write_special_header(out, COFF_LONG_NAMES_NAME, long_names.len() as u64)?;
+    out.write_all(&long_names)?;
+    pad_to_even(out, long_names.len() as u64)?;
+
+    for (member, name) in members.iter().zip(&names) {
+        write_header(
+            out,
+            name,
+            member.mtime,
+            member.uid,
+            member.gid,
+            member.mode,
+            member.size,
+        )?;
+        write_member_data(out, member)?;
+        pad_to_even(out, member.size)?;
+    }
+    Ok(())
+}
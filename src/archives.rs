@@ -1,19 +1,20 @@
 use crate::arbuilder::ArBuilder;
 use crate::input_library::InputLibrary;
 use crate::objects::ObjectTempDir;
-use crate::MergeError::ExternalToolLaunchError;
+use crate::symbol_rules::SymbolRules;
 use crate::{MergeError, ProcessInputError};
 use ar::Archive;
 use goblin::{peek_bytes, Hint};
-use rand::distr::{Alphanumeric, SampleString};
-use rand::rng;
 use rayon::prelude::*;
-use std::ffi::OsString;
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::fs::File;
-use std::io::{Read, Write};
-use std::str::FromStr;
-use tracing::info;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const AR_MAGIC: &[u8; 8] = b"!<arch>\n";
+const THIN_ARCHIVE_MAGIC: &[u8; 8] = b"!<thin>\n";
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ArchiveContents {
@@ -21,7 +22,9 @@ pub enum ArchiveContents {
     Elf,
     /// Only Mach-O files
     MachO,
-    /// Only unsupported files (e.g. PE/COFF)
+    /// Only PE/COFF files (MSVC `.lib` members, including import library members)
+    Coff,
+    /// Only unsupported files
     Other,
 
     /// Archives contain a mix of file types
@@ -62,136 +65,319 @@ impl Debug for ExtractedArchive {
 }
 
 fn archive_object_type(object_header: &[u8; 16]) -> ArchiveContents {
+    // Import library members and /bigobj objects both start with an "anonymous object" header
+    if object_header.starts_with(&[0x00, 0x00, 0xff, 0xff]) {
+        return ArchiveContents::Coff;
+    }
     match peek_bytes(object_header) {
         Ok(Hint::Elf(_)) => ArchiveContents::Elf,
         Ok(Hint::Mach(_) | Hint::MachFat(_)) => ArchiveContents::MachO,
+        Ok(Hint::COFF) => ArchiveContents::Coff,
         Ok(_) => ArchiveContents::Other,
         Err(_) => ArchiveContents::Other, // Malformed input
     }
 }
 
-pub fn extract_objects<I: IntoParallelIterator<Item = InputLibrary<R>>, R: Read>(
+/// The objects extracted so far, from one or more input libraries
+struct ExtractedObjects {
+    objects: Vec<PathBuf>,
+    origins: HashMap<PathBuf, PathBuf>,
+    names: HashMap<PathBuf, String>,
+    libraries: HashMap<PathBuf, String>,
+    library_rules: HashMap<String, SymbolRules>,
+    contents_type: ArchiveContents,
+    /// Whether objects are kept in `in_memory` instead of being written to the objects dir
+    keep_in_memory: bool,
+    in_memory: HashMap<PathBuf, Arc<[u8]>>,
+}
+
+impl ExtractedObjects {
+    fn new(keep_in_memory: bool) -> Self {
+        Self {
+            objects: Vec::new(),
+            origins: HashMap::new(),
+            names: HashMap::new(),
+            libraries: HashMap::new(),
+            library_rules: HashMap::new(),
+            contents_type: ArchiveContents::Empty,
+            keep_in_memory,
+            in_memory: HashMap::new(),
+        }
+    }
+
+    fn append(mut self, mut other: Self) -> Self {
+        self.objects.append(&mut other.objects);
+        self.origins.extend(other.origins);
+        self.names.extend(other.names);
+        self.libraries.extend(other.libraries);
+        self.library_rules.extend(other.library_rules);
+        self.in_memory.extend(other.in_memory);
+        self.contents_type = ArchiveContents::merge(self.contents_type, other.contents_type);
+        self
+    }
+}
+
+/// Extracts the objects of the input libraries to a temporary directory,
+/// or only in memory with `in_memory` until an external tool needs them as files
+pub fn extract_objects<I: IntoParallelIterator<Item = InputLibrary<R>>, R: Read + Send>(
     input_libraries: I,
+    in_memory: bool,
 ) -> Result<ExtractedArchive, ProcessInputError> {
     let dir = tempfile::Builder::new()
         .prefix("armerge.")
         .tempdir()
         .map_err(ProcessInputError::TempDir)?;
 
-    let (objects, archive_contents) = input_libraries
+    // Objects are named after the position of their library in the inputs, so that the same
+    // inputs are always extracted to the same paths, even when two members have the same name
+    let input_libraries = input_libraries.into_par_iter().collect::<Vec<_>>();
+    let extracted = input_libraries
         .into_par_iter()
+        .enumerate()
         .try_fold(
-            || (Vec::new(), ArchiveContents::Empty),
-            |(mut objects, mut archive_contents), input_lib| {
-                let mut archive = Archive::new(input_lib.reader);
-                while let Some(entry_result) = archive.next_entry() {
-                    let mut entry =
-                        entry_result.map_err(|e| ProcessInputError::ReadingArchive {
-                            name: input_lib.name.clone(),
-                            inner: e,
-                        })?;
-
-                    let rnd: String = Alphanumeric.sample_string(&mut rng(), 8);
-                    let mut obj_path = dir.path().to_owned();
-                    obj_path.push(format!(
-                        "{}@{}.{}.o",
-                        input_lib.name,
-                        String::from_utf8_lossy(entry.header().identifier()),
-                        &rnd
-                    ));
-
-                    let hint_bytes = &mut [0u8; 16];
-                    entry.read_exact(hint_bytes).map_err(|e| {
-                        ProcessInputError::ReadingArchive {
-                            name: input_lib.name.clone(),
-                            inner: e,
-                        }
-                    })?;
-                    let obj_type = archive_object_type(hint_bytes);
-                    archive_contents = ArchiveContents::merge(archive_contents, obj_type);
-
-                    let mut file = File::create(&obj_path).map_err(|e| {
-                        ProcessInputError::ExtractingObject {
-                            path: obj_path.to_owned(),
-                            inner: e,
-                        }
-                    })?;
-                    file.write_all(hint_bytes).map_err(|e| {
-                        ProcessInputError::ExtractingObject {
-                            path: obj_path.to_owned(),
-                            inner: e,
-                        }
-                    })?;
-                    std::io::copy(&mut entry, &mut file).map_err(|e| {
-                        ProcessInputError::ExtractingObject {
-                            path: obj_path.to_owned(),
-                            inner: e,
-                        }
-                    })?;
-                    objects.push(obj_path);
+            || ExtractedObjects::new(in_memory),
+            |mut extracted, (lib_idx, mut input_lib)| {
+                let first_object = extracted.objects.len();
+                extract_library(
+                    dir.path(),
+                    &input_lib.name,
+                    &lib_idx.to_string(),
+                    &mut input_lib.reader,
+                    input_lib.base_dir.as_deref(),
+                    &mut extracted,
+                )?;
+                for obj_path in &extracted.objects[first_object..] {
+                    extracted
+                        .libraries
+                        .insert(obj_path.to_owned(), input_lib.name.to_owned());
                 }
-
-                Ok((objects, archive_contents))
+                if let Some(rules) = input_lib.symbol_rules.take() {
+                    extracted.library_rules.insert(input_lib.name, rules);
+                }
+                Ok(extracted)
             },
         )
-        .try_reduce(
-            || (Vec::new(), ArchiveContents::Empty),
-            |(mut objs_a, contents_a), (mut objs_b, contents_b)| {
-                objs_a.append(&mut objs_b);
-                Ok((objs_a, ArchiveContents::merge(contents_a, contents_b)))
-            },
-        )?;
+        .try_reduce(|| ExtractedObjects::new(in_memory), |a, b| Ok(a.append(b)))?;
 
     Ok(ExtractedArchive {
-        object_dir: ObjectTempDir { dir, objects },
-        contents_type: archive_contents,
+        object_dir: ObjectTempDir {
+            dir,
+            objects: extracted.objects,
+            origins: extracted.origins,
+            names: extracted.names,
+            libraries: extracted.libraries,
+            library_rules: extracted.library_rules,
+            in_memory: extracted.in_memory,
+        },
+        contents_type: extracted.contents_type,
     })
 }
 
-pub fn get_object_name_from_path(path: &std::path::Path) -> String {
-    let filename = path.file_name().unwrap().to_string_lossy();
-    let name_parts = filename.rsplitn(3, '.').collect::<Vec<_>>();
-    name_parts[2].to_string()
+/// Extracts all the members of a library, including the members of nested libraries.
+/// Members of a nested library are named after the full chain of libraries containing them.
+/// `lib_id` uniquely identifies the library, it is used to make the names of its members unique.
+fn extract_library(
+    dir: &Path,
+    lib_name: &str,
+    lib_id: &str,
+    reader: &mut dyn Read,
+    base_dir: Option<&Path>,
+    extracted: &mut ExtractedObjects,
+) -> Result<(), ProcessInputError> {
+    let reading_error = |e| ProcessInputError::ReadingArchive {
+        name: lib_name.to_owned(),
+        inner: e,
+    };
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).map_err(reading_error)?;
+    if &magic == THIN_ARCHIVE_MAGIC {
+        // Thin archives only reference their members, which live next to the archive
+        let base_dir = base_dir.ok_or_else(|| ProcessInputError::ThinArchiveWithoutBaseDir {
+            name: lib_name.to_owned(),
+        })?;
+        let members = read_thin_archive_members(reader, base_dir).map_err(reading_error)?;
+        for (member_idx, member_path) in members.into_iter().enumerate() {
+            let mut file = File::open(&member_path).map_err(|e| ProcessInputError::FileOpen {
+                path: member_path.to_owned(),
+                inner: e,
+            })?;
+            let member_name = member_path
+                .file_name()
+                .unwrap_or(member_path.as_os_str())
+                .to_string_lossy();
+            let obj_path = extract_member(
+                dir,
+                lib_name,
+                &format!("{lib_id}-{member_idx}"),
+                member_name.as_bytes(),
+                &mut file,
+                // A nested thin archive's members are relative to that archive, not to this one
+                member_path.parent(),
+                extracted,
+            )?;
+            if let Some(obj_path) = obj_path {
+                extracted.origins.insert(obj_path, member_path);
+            }
+        }
+    } else {
+        let mut archive = Archive::new(Cursor::new(magic).chain(reader));
+        let mut member_idx = 0;
+        while let Some(entry_result) = archive.next_entry() {
+            let mut entry = entry_result.map_err(reading_error)?;
+            let member_name = entry.header().identifier().to_owned();
+            let member_id = format!("{lib_id}-{member_idx}");
+            extract_member(
+                dir,
+                lib_name,
+                &member_id,
+                &member_name,
+                &mut entry,
+                base_dir,
+                extracted,
+            )?;
+            member_idx += 1;
+        }
+    }
+    Ok(())
 }
 
-pub fn create_index(archive_path: &std::path::Path) -> Result<(), MergeError> {
-    use std::process::Command;
+/// Copies an archive member to the objects dir and returns its path,
+/// or extracts its own members if the member is itself a library.
+fn extract_member(
+    dir: &Path,
+    lib_name: &str,
+    member_id: &str,
+    member_name: &[u8],
+    reader: &mut dyn Read,
+    base_dir: Option<&Path>,
+    extracted: &mut ExtractedObjects,
+) -> Result<Option<PathBuf>, ProcessInputError> {
+    let hint_bytes = &mut [0u8; 16];
+    reader
+        .read_exact(hint_bytes)
+        .map_err(|e| ProcessInputError::ReadingArchive {
+            name: lib_name.to_owned(),
+            inner: e,
+        })?;
 
-    let ranlib_path = if let Some(var) = std::env::var_os("RANLIB") {
-        var
-    } else {
-        OsString::from_str("ranlib").unwrap()
+    if hint_bytes.starts_with(AR_MAGIC) || hint_bytes.starts_with(THIN_ARCHIVE_MAGIC) {
+        let nested_name = format!("{}@{}", lib_name, String::from_utf8_lossy(member_name));
+        let mut nested_reader = Cursor::new(*hint_bytes).chain(reader);
+        extract_library(
+            dir,
+            &nested_name,
+            member_id,
+            &mut nested_reader,
+            base_dir,
+            extracted,
+        )?;
+        return Ok(None);
+    }
+
+    let mut obj_path = dir.to_owned();
+    obj_path.push(format!(
+        "{}@{}.{}.o",
+        lib_name,
+        String::from_utf8_lossy(member_name),
+        member_id
+    ));
+
+    let obj_type = archive_object_type(hint_bytes);
+    extracted.contents_type = ArchiveContents::merge(extracted.contents_type, obj_type);
+
+    let extracting_error = |e| ProcessInputError::ExtractingObject {
+        path: obj_path.to_owned(),
+        inner: e,
     };
+    if extracted.keep_in_memory {
+        let mut data = hint_bytes.to_vec();
+        reader.read_to_end(&mut data).map_err(extracting_error)?;
+        extracted.in_memory.insert(obj_path.clone(), data.into());
+    } else {
+        let mut file = File::create(&obj_path).map_err(extracting_error)?;
+        file.write_all(hint_bytes).map_err(extracting_error)?;
+        std::io::copy(reader, &mut file).map_err(extracting_error)?;
+    }
 
-    info!(
-        "{} {}",
-        ranlib_path.to_string_lossy(),
-        archive_path.to_string_lossy()
+    extracted.objects.push(obj_path.clone());
+    extracted.names.insert(
+        obj_path.clone(),
+        String::from_utf8_lossy(member_name).into_owned(),
     );
+    Ok(Some(obj_path))
+}
 
-    let output = Command::new(&ranlib_path)
-        .args(vec![archive_path])
-        .output()
-        .map_err(|e| ExternalToolLaunchError {
-            tool: ranlib_path.to_string_lossy().to_string(),
-            inner: e,
-        })?;
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(MergeError::ExternalToolError {
-            reason: "Failed to create archive index".to_string(),
-            tool: ranlib_path.to_string_lossy().to_string(),
-            args: archive_path.iter().map(|p| p.to_owned()).collect(),
-            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-        })
+/// Reads the headers of a GNU thin archive (after its magic), and returns the paths of its members.
+/// Only the symbol table and long names table have their data in the archive itself.
+fn read_thin_archive_members(reader: &mut dyn Read, base_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let invalid_data = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+    let mut long_names = Vec::new();
+    let mut members = Vec::new();
+    let mut header = [0u8; 60];
+    loop {
+        if reader.read(&mut header[..1])? == 0 {
+            break;
+        }
+        reader.read_exact(&mut header[1..])?;
+        if &header[58..] != b"`\n" {
+            return Err(invalid_data("invalid thin archive member header"));
+        }
+        let name = header[..16].trim_ascii_end();
+        let size = std::str::from_utf8(&header[48..58])
+            .ok()
+            .and_then(|size| size.trim_end().parse::<u64>().ok())
+            .ok_or_else(|| invalid_data("invalid thin archive member size"))?;
+
+        match name {
+            b"/" | b"/SYM64/" => {
+                io::copy(&mut reader.take(size + size % 2), &mut io::sink())?;
+            }
+            b"//" => {
+                long_names = vec![0; (size + size % 2) as usize];
+                reader.read_exact(&mut long_names)?;
+            }
+            _ => {
+                let member_name = match name.strip_prefix(b"/") {
+                    Some(offset) => {
+                        let offset = std::str::from_utf8(offset)
+                            .ok()
+                            .and_then(|offset| offset.parse::<usize>().ok())
+                            .filter(|&offset| offset < long_names.len())
+                            .ok_or_else(|| invalid_data("invalid thin archive member name"))?;
+                        let long_name = &long_names[offset..];
+                        let end = long_name
+                            .windows(2)
+                            .position(|w| w == b"/\n")
+                            .unwrap_or(long_name.len());
+                        &long_name[..end]
+                    }
+                    None => name.strip_suffix(b"/").unwrap_or(name),
+                };
+                members.push(base_dir.join(String::from_utf8_lossy(member_name).as_ref()));
+            }
+        }
     }
+    Ok(members)
+}
+
+pub fn get_object_name_from_path(path: &Path) -> String {
+    let filename = path.file_name().unwrap().to_string_lossy();
+    let name_parts = filename.rsplitn(3, '.').collect::<Vec<_>>();
+    name_parts[2].to_string()
 }
 
 pub fn merge(mut output: Box<dyn ArBuilder>, objects_dir: ObjectTempDir) -> Result<(), MergeError> {
     for obj_path in objects_dir.objects {
-        output.append_obj(obj_path.as_path())?;
+        let name = objects_dir.names[&obj_path].as_str();
+        let origin = objects_dir.origins.get(&obj_path);
+        let data = objects_dir.in_memory.get(&obj_path).cloned();
+        output.append_extracted_obj(
+            obj_path.as_path(),
+            name,
+            origin.map(PathBuf::as_path),
+            data,
+        )?;
     }
     output.close()?;
     Ok(())
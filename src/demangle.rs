@@ -0,0 +1,132 @@
+use crate::objects::ObjectTempDir;
+use crate::MergeError;
+use object::{Object, ObjectSymbol, SymbolKind};
+use rayon::prelude::*;
+use regex::{Regex, RegexBuilder};
+use std::collections::BTreeSet;
+use tracing::{event_enabled, info, Level};
+
+/// Demangles Rust (legacy and v0) and Itanium C++ symbol names, without the hash of Rust symbols.
+/// Mach-O symbols are accepted with their extra leading underscore.
+pub fn demangle(name: &str) -> Option<String> {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return Some(format!("{demangled:#}"));
+    }
+    let itanium = match name.strip_prefix('_') {
+        Some(macho_name) if macho_name.starts_with("_Z") => macho_name,
+        _ => name,
+    };
+    if !itanium.starts_with("_Z") {
+        return None;
+    }
+    let symbol = cpp_demangle::Symbol::new(itanium).ok()?;
+    symbol
+        .demangle(&cpp_demangle::DemangleOptions::default())
+        .ok()
+}
+
+/// The demangled name followed by the mangled one, or just the name if it isn't mangled
+pub fn display_name(name: &str) -> String {
+    match demangle(name) {
+        Some(demangled) => format!("{demangled} ({name})"),
+        None => name.to_owned(),
+    }
+}
+
+/// The global symbols defined in the objects whose demangled name matches one of the regexes.
+/// When `include_unmangled` is set, symbols that aren't mangled are matched by their plain name.
+pub fn matching_symbols(
+    objects: &ObjectTempDir,
+    regexes: &[Regex],
+    include_unmangled: bool,
+) -> Result<BTreeSet<String>, MergeError> {
+    let symbols = symbols_matching_each(objects, regexes, include_unmangled)?;
+    Ok(symbols.into_iter().flatten().collect())
+}
+
+/// The global symbols defined in the objects whose demangled name matches each of the regexes
+fn symbols_matching_each(
+    objects: &ObjectTempDir,
+    regexes: &[Regex],
+    include_unmangled: bool,
+) -> Result<Vec<BTreeSet<String>>, MergeError> {
+    let symbols = objects
+        .objects
+        .par_iter()
+        .map(|path| {
+            let data = objects.read(path)?;
+            let file = object::File::parse(&*data).map_err(|e| MergeError::InvalidObject {
+                path: path.to_owned(),
+                inner: e,
+            })?;
+            let mut matching = Vec::new();
+            for sym in file.symbols() {
+                if !sym.is_global()
+                    || sym.is_undefined()
+                    || sym.kind() == SymbolKind::Section
+                    || sym.kind() == SymbolKind::File
+                {
+                    continue;
+                }
+                let Ok(name) = sym.name() else {
+                    continue;
+                };
+                let demangled = match demangle(name) {
+                    Some(demangled) => demangled,
+                    None if include_unmangled => name.to_owned(),
+                    None => continue,
+                };
+                for (idx, regex) in regexes.iter().enumerate() {
+                    if regex.is_match(&demangled) {
+                        matching.push((idx, name.to_owned()));
+                    }
+                }
+            }
+            Ok::<_, MergeError>(matching)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut matching = vec![BTreeSet::new(); regexes.len()];
+    for (idx, name) in symbols.into_iter().flatten() {
+        matching[idx].insert(name);
+    }
+    Ok(matching)
+}
+
+/// Replaces each regex written against demangled names by a regex matching the mangled names
+/// of the symbols it selects, which is what the rest of the merge works with.
+/// Regexes that select no symbol are replaced by `None`.
+pub fn mangled_regexes(
+    objects: &ObjectTempDir,
+    regexes: &[Regex],
+) -> Result<Vec<Option<Regex>>, MergeError> {
+    let matching = symbols_matching_each(objects, regexes, true)?;
+    let symbols = matching.iter().flatten().collect::<BTreeSet<_>>();
+    info!(
+        "{} symbols match the patterns after demangling",
+        symbols.len()
+    );
+    if event_enabled!(Level::INFO) {
+        for name in &symbols {
+            info!("Symbol `{}` matches", display_name(name));
+        }
+    }
+
+    matching
+        .into_iter()
+        .map(|symbols| {
+            if symbols.is_empty() {
+                return Ok(None);
+            }
+            let alternatives = symbols
+                .iter()
+                .map(|name| regex::escape(name))
+                .collect::<Vec<_>>()
+                .join("|");
+            RegexBuilder::new(&format!("^(?:{alternatives})$"))
+                .size_limit(usize::MAX)
+                .build()
+                .map(Some)
+                .map_err(|e| MergeError::InternalError(e.into()))
+        })
+        .collect()
+}
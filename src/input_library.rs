@@ -1,10 +1,14 @@
+use crate::symbol_rules::SymbolRules;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
 /// A static library (ar archive) to be merged
 #[derive(Debug)]
 pub struct InputLibrary<R: Read> {
     pub(crate) name: String,
     pub(crate) reader: R,
+    pub(crate) base_dir: Option<PathBuf>,
+    pub(crate) symbol_rules: Option<SymbolRules>,
 }
 
 impl<R: Read> InputLibrary<R> {
@@ -14,12 +18,36 @@ impl<R: Read> InputLibrary<R> {
         Self {
             name: name.into(),
             reader,
+            base_dir: None,
+            symbol_rules: None,
         }
     }
+
+    /// The directory that the member paths of a thin archive are relative to,
+    /// usually the directory containing the library. Thin archives can't be read without it.
+    pub fn with_base_dir<P: Into<PathBuf>>(mut self, base_dir: P) -> Self {
+        self.base_dir = Some(base_dir.into());
+        self
+    }
+
+    /// The rules deciding which global symbols of this library's objects stay global,
+    /// instead of the rules given when merging
+    pub fn with_symbol_rules(mut self, rules: SymbolRules) -> Self {
+        self.symbol_rules = Some(rules);
+        self
+    }
 }
 
 impl<R: Read> InputLibrary<R> {
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    pub fn base_dir(&self) -> Option<&Path> {
+        self.base_dir.as_deref()
+    }
+
+    pub fn symbol_rules(&self) -> Option<&SymbolRules> {
+        self.symbol_rules.as_ref()
+    }
 }
@@ -1,17 +1,29 @@
 mod arbuilder;
 mod archives;
+mod demangle;
 mod input_library;
 mod merge_error;
+mod merge_options;
 mod objects;
 mod process_input_error;
+mod symbol_rules;
+mod symbol_list;
+mod symbol_list_error;
+mod version_script;
+mod version_script_error;
 
 use crate::arbuilder::common::CommonArBuilder;
 use crate::arbuilder::mac::MacArBuilder;
+use crate::arbuilder::thin::ThinArBuilder;
 use crate::arbuilder::ArBuilder;
 use crate::archives::{ArchiveContents, ExtractedArchive};
 pub use crate::input_library::InputLibrary;
 use crate::merge_error::MergeError;
+pub use crate::merge_options::MergeOptions;
 use crate::process_input_error::ProcessInputError;
+pub use crate::symbol_list::SymbolList;
+pub use crate::symbol_rules::{SymbolAction, SymbolRules};
+pub use crate::version_script::VersionScript;
 use rayon::prelude::*;
 use regex::Regex;
 use std::fs::File;
@@ -23,6 +35,7 @@ use tracing::error;
 pub struct ArMerger {
     extracted: ExtractedArchive,
     builder: Box<dyn ArBuilder>,
+    options: MergeOptions,
 }
 
 #[derive(PartialEq, Eq, Copy, Clone)]
@@ -33,19 +46,57 @@ pub enum ArmergeKeepOrRemove {
 
 impl ArMerger {
     /// Open and extract the contents of the input static libraries
-    pub fn new<I: IntoParallelIterator<Item = InputLibrary<R>>, R: Read, O: AsRef<Path>>(
+    pub fn new<I: IntoParallelIterator<Item = InputLibrary<R>>, R: Read + Send, O: AsRef<Path>>(
         input_libs: I,
         output: O,
     ) -> Result<Self, ProcessInputError> {
-        let extracted = archives::extract_objects(input_libs)?;
-        let builder = Self::create_ar_builder(extracted.contents_type, output)?;
-        Ok(Self { extracted, builder })
+        Self::new_with_options(input_libs, output, MergeOptions::default())
+    }
+
+    /// Open and extract the contents of the input static libraries, with non-default merge options
+    pub fn new_with_options<
+        I: IntoParallelIterator<Item = InputLibrary<R>>,
+        R: Read + Send,
+        O: AsRef<Path>,
+    >(
+        input_libs: I,
+        output: O,
+        options: MergeOptions,
+    ) -> Result<Self, ProcessInputError> {
+        let mut extracted = archives::extract_objects(input_libs, options.in_memory)?;
+        for (name, rules) in &options.library_rules {
+            extracted
+                .object_dir
+                .library_rules
+                .entry(name.to_owned())
+                .or_insert_with(|| rules.clone());
+        }
+        let builder = Self::create_ar_builder(extracted.contents_type, output, &options)?;
+        Ok(Self {
+            extracted,
+            builder,
+            options,
+        })
     }
 
     /// Open and extract the contents of the input static libraries at the given paths
     pub fn new_from_paths<I: IntoIterator<Item = P>, P: AsRef<Path>, O: AsRef<Path>>(
         input_paths: I,
         output_path: O,
+    ) -> Result<Self, ProcessInputError> {
+        Self::new_from_paths_with_options(input_paths, output_path, MergeOptions::default())
+    }
+
+    /// Open and extract the contents of the input static libraries at the given paths,
+    /// with non-default merge options
+    pub fn new_from_paths_with_options<
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+        O: AsRef<Path>,
+    >(
+        input_paths: I,
+        output_path: O,
+        options: MergeOptions,
     ) -> Result<Self, ProcessInputError> {
         let libs: Result<Vec<InputLibrary<File>>, _> = input_paths
             .into_iter()
@@ -56,8 +107,9 @@ impl ArMerger {
                     .unwrap_or(path.as_os_str())
                     .to_string_lossy()
                     .replace('/', "_");
+                let base_dir = path.parent().unwrap_or(Path::new(""));
                 match File::open(path) {
-                    Ok(f) => Ok(InputLibrary::new(filename, f)),
+                    Ok(f) => Ok(InputLibrary::new(filename, f).with_base_dir(base_dir)),
                     Err(e) => Err(ProcessInputError::FileOpen {
                         path: path.to_owned(),
                         inner: e,
@@ -65,24 +117,35 @@ impl ArMerger {
                 }
             })
             .collect();
-        Self::new(libs?, output_path)
+        Self::new_with_options(libs?, output_path, options)
     }
 
     fn create_ar_builder<P: AsRef<Path>>(
         contents_type: ArchiveContents,
         output: P,
+        options: &MergeOptions,
     ) -> Result<Box<dyn ArBuilder>, ProcessInputError> {
+        let output = output.as_ref();
+        let deterministic = options.deterministic;
+        if options.thin_archive {
+            return match contents_type {
+                ArchiveContents::Empty => Err(ProcessInputError::Empty),
+                ArchiveContents::Elf => Ok(Box::new(ThinArBuilder::new(output, deterministic))),
+                _ => Err(ProcessInputError::ThinArchiveUnsupported),
+            };
+        }
         Ok(match contents_type {
             ArchiveContents::Empty => return Err(ProcessInputError::Empty),
-            ArchiveContents::Elf => Box::new(CommonArBuilder::new(output.as_ref())),
-            ArchiveContents::MachO => Box::new(MacArBuilder::new(output.as_ref())),
+            ArchiveContents::Elf => Box::new(CommonArBuilder::new(output, deterministic)),
+            ArchiveContents::MachO => Box::new(MacArBuilder::new(output, deterministic)),
+            ArchiveContents::Coff => Box::new(CommonArBuilder::new(output, deterministic)),
             ArchiveContents::Other => {
-                error!("Input archives contain neither ELF nor Mach-O files, trying to continue with your host toolchain");
-                arbuilder::host_platform_builder(output.as_ref())
+                error!("Input archives contain neither ELF, Mach-O nor COFF files, trying to continue with your host toolchain");
+                arbuilder::host_platform_builder(output, deterministic)
             }
             ArchiveContents::Mixed => {
                 error!("Input archives contain different object file formats, trying to continue with your host toolchain");
-                arbuilder::host_platform_builder(output.as_ref())
+                arbuilder::host_platform_builder(output, deterministic)
             }
         })
     }
@@ -115,14 +178,81 @@ impl ArMerger {
         keep_or_remove: ArmergeKeepOrRemove,
         symbols_regexes: Iter,
         object_order: impl IntoIterator<Item = String>
+    ) -> Result<(), MergeError> {
+        self.merge_with_rules(
+            SymbolRules::from_keep_or_remove(keep_or_remove, symbols_regexes),
+            object_order,
+        )
+    }
+
+    /// Merge input libraries in a specified order, and localize symbols following an ordered list of rules
+    /// `object_order` contains the order in which certain object files will be merged
+    pub fn merge_with_rules(
+        self,
+        rules: SymbolRules,
+        object_order: impl IntoIterator<Item = String>,
     ) -> Result<(), MergeError> {
         objects::merge(
             self.builder,
             self.extracted.contents_type,
             self.extracted.object_dir,
-            keep_or_remove,
-            symbols_regexes.into_iter().collect(),
-            object_order.into_iter().enumerate().map(|(i, s)| (s, i)).collect()
+            rules,
+            object_order.into_iter().enumerate().map(|(i, s)| (s, i)).collect(),
+            &self.options,
         )
     }
+
+    /// Explain why the objects or global symbols named by `queries` would be merged or dropped,
+    /// and kept global or localized, without merging anything.
+    /// Queries are member names, `library@member` object names, or mangled or demangled symbol names.
+    pub fn explain<Iter: IntoIterator<Item = Regex>>(
+        self,
+        keep_or_remove: ArmergeKeepOrRemove,
+        symbols_regexes: Iter,
+        queries: &[String],
+    ) -> Result<Vec<String>, MergeError> {
+        self.explain_with_rules(
+            SymbolRules::from_keep_or_remove(keep_or_remove, symbols_regexes),
+            queries,
+        )
+    }
+
+    /// Explain the objects and symbols named by `queries`, like [`ArMerger::explain`], for an ordered list of rules
+    pub fn explain_with_rules(
+        self,
+        rules: SymbolRules,
+        queries: &[String],
+    ) -> Result<Vec<String>, MergeError> {
+        objects::explain(self.extracted.object_dir, rules, queries, &self.options)
+    }
+
+    /// Explain the objects and symbols named by `queries`, like [`ArMerger::explain`], for a GNU ld version script
+    pub fn explain_with_version_script(
+        mut self,
+        version_script: &VersionScript,
+        queries: &[String],
+    ) -> Result<Vec<String>, MergeError> {
+        let (keep_or_remove, regexes) = version_script.symbol_policy(
+            &self.extracted.object_dir,
+            self.extracted.contents_type,
+        )?;
+        self.options.demangle = false;
+        self.explain(keep_or_remove, regexes, queries)
+    }
+
+    /// Merge input libraries in a specified order, and localize symbols following a GNU ld version script
+    /// `object_order` contains the order in which certain object files will be merged
+    pub fn merge_with_version_script(
+        mut self,
+        version_script: &VersionScript,
+        object_order: impl IntoIterator<Item = String>,
+    ) -> Result<(), MergeError> {
+        let (keep_or_remove, regexes) = version_script.symbol_policy(
+            &self.extracted.object_dir,
+            self.extracted.contents_type,
+        )?;
+        // The script's patterns are already resolved to mangled names
+        self.options.demangle = false;
+        self.merge_and_localize_ordered(keep_or_remove, regexes, object_order)
+    }
 }
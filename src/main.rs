@@ -1,10 +1,11 @@
-use armerge::{ArmergeKeepOrRemove, ArMerger};
+use armerge::{ArMerger, MergeOptions, SymbolAction, SymbolList, SymbolRules, VersionScript};
 use regex::Regex;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
 use tracing::{error, Level};
 use tracing_subscriber::filter::Directive;
 use tracing_subscriber::fmt::time::UtcTime;
@@ -12,22 +13,109 @@ use tracing_subscriber::fmt::time::UtcTime;
 #[derive(Parser, Debug)]
 #[command(version, about)]
 struct Opt {
-    /// Accepts regexes of the symbol names to keep global, and localizes the rest
+    /// Accepts regexes of the symbol names to keep global, and localizes the rest.
+    /// With -r, the first matching -k or -r pattern on the command line decides
     #[arg(short, long, num_args = 1)]
     keep_symbols: Vec<String>,
 
-    /// Accepts regexes of the symbol names to hide, and keep the rest global
+    /// Accepts regexes of the symbol names to hide, and keep the rest global.
+    /// With -k, the first matching -k or -r pattern on the command line decides
     #[arg(short, long, num_args = 1)]
     remove_symbols: Vec<String>,
 
+    /// Darwin `-exported_symbols_list` file of symbols to keep global, like -k after all -k and -r patterns
+    #[arg(long, num_args = 1)]
+    exported_symbols_list: Vec<PathBuf>,
+
+    /// Windows module-definition (.def) file whose EXPORTS are kept global, like -k after all -k and -r patterns
+    #[arg(long, num_args = 1)]
+    def_file: Vec<PathBuf>,
+
+    /// GNU ld version script whose global and local symbols are used instead of -k or -r
+    #[arg(long, conflicts_with_all = ["keep_symbols", "remove_symbols", "exported_symbols_list", "def_file"])]
+    version_script: Option<PathBuf>,
+
+    /// Symbol rule for the objects of one input library, as `libname.a=keep:REGEX` or `libname.a=localize:REGEX`,
+    /// used instead of -k and -r for that library. Without a regex, the rule matches every symbol
+    #[arg(long, num_args = 1)]
+    policy: Vec<String>,
+
+    /// Match the -k and -r regexes against demangled C++ and Rust symbol names
+    #[arg(long)]
+    demangle: bool,
+
+    /// Decide which objects and symbols are kept, without writing the output (requires --report)
+    #[arg(long, requires = "report")]
+    dry_run: bool,
+
+    /// Write a JSON report of the merged and dropped objects, and of the kept and localized symbols
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Write the object dependency graph, as JSON if the file ends with `.json` or else as Graphviz
+    #[arg(long)]
+    emit_dep_graph: Option<PathBuf>,
+
+    /// Print why an object (member name) or a global symbol is merged or dropped, kept or localized, without merging
+    #[arg(long, num_args = 1, conflicts_with_all = ["dry_run", "report", "emit_dep_graph"])]
+    why: Vec<String>,
+
+    /// File of regexes, one per line, of the external symbols the merged library may use; any other undefined symbol fails the merge
+    #[arg(long)]
+    allow_undefined: Option<PathBuf>,
+
     /// Order file to control the sorting of merged objects
     #[arg(long)]
     order_file: Option<PathBuf>,
 
+    /// Prefix added to the global symbols of the merged object (requires -k, -r or --version-script)
+    #[arg(long)]
+    prefix_symbols: Option<String>,
+
+    /// File of `old new` symbol name pairs to rename in the merged object (requires -k, -r or --version-script)
+    #[arg(long)]
+    rename_symbols: Option<PathBuf>,
+
     /// Output static library
     #[arg(short, long)]
     output: PathBuf,
 
+    /// Merge objects with the system linker (`$LD -r`) instead of the builtin ELF linker
+    #[arg(long)]
+    system_linker: bool,
+
+    /// Write a GNU thin archive that references the input objects in place
+    #[arg(long)]
+    thin: bool,
+
+    /// Write a reproducible archive, with zeroed timestamps, uids and gids
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Give the symbols that are not kept hidden visibility (private extern on Mach-O) instead of localizing them
+    #[arg(long)]
+    hidden_visibility: bool,
+
+    /// Drop the ELF sections that the kept symbols don't reach, using the system linker (GNU ld)
+    #[arg(long)]
+    gc_sections: bool,
+
+    /// Remove the debug info of the merged object (requires -k, -r or --version-script)
+    #[arg(long)]
+    strip_debug: bool,
+
+    /// Write the debug info of the merged object to this file instead of the output (requires -k, -r or --version-script)
+    #[arg(long, conflicts_with = "strip_debug")]
+    split_debug: Option<PathBuf>,
+
+    /// Keep the extracted objects in memory instead of a temporary directory, and only write them for external tools
+    #[arg(long)]
+    in_memory: bool,
+
+    /// Only merge the first of several copies of an object found in different input libraries
+    #[arg(long)]
+    drop_duplicate_objects: bool,
+
     /// Print verbose information
     #[arg(short, long)]
     verbose: bool,
@@ -44,7 +132,9 @@ fn main() {
         std::env::set_var("RUST_LOG", "warn")
     }
 
-    let opt = Opt::parse();
+    let matches = Opt::command().get_matches();
+    let symbol_rules = ordered_symbol_rules(&matches);
+    let opt = Opt::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     let mut filter = tracing_subscriber::EnvFilter::from_default_env();
     if opt.verbose {
         filter = filter.add_directive(Directive::from(Level::INFO));
@@ -55,58 +145,182 @@ fn main() {
         .with_env_filter(filter)
         .init();
 
-    if let Err(e) = err_main(opt) {
+    if let Err(e) = err_main(opt, symbol_rules) {
         error!("{}", e);
         std::process::exit(1);
     }
 }
 
-fn err_main(opt: Opt) -> Result<(), Box<dyn Error>> {
+/// The -k and -r patterns, in the order they appear on the command line
+fn ordered_symbol_rules(matches: &ArgMatches) -> Vec<(SymbolAction, String)> {
+    let mut rules = Vec::new();
+    for (id, action) in [("keep_symbols", SymbolAction::Keep), ("remove_symbols", SymbolAction::Localize)] {
+        if let (Some(indices), Some(patterns)) = (matches.indices_of(id), matches.get_many::<String>(id)) {
+            rules.extend(indices.zip(patterns).map(|(index, pattern)| (index, action, pattern.clone())));
+        }
+    }
+    rules.sort_by_key(|(index, _, _)| *index);
+    rules.into_iter().map(|(_, action, pattern)| (action, pattern)).collect()
+}
+
+fn err_main(opt: Opt, symbol_rules: Vec<(SymbolAction, String)>) -> Result<(), Box<dyn Error>> {
     if opt.inputs.is_empty() {
         return Err("No input file specified".to_string().into());
     }
 
-    let merger = ArMerger::new_from_paths(&opt.inputs, &opt.output)?;
+    let mut options = MergeOptions::default();
+    options.use_system_linker = opt.system_linker;
+    options.thin_archive = opt.thin;
+    options.deterministic = opt.deterministic;
+    options.in_memory = opt.in_memory;
+    options.hidden_visibility = opt.hidden_visibility;
+    options.gc_sections = opt.gc_sections;
+    options.strip_debug = opt.strip_debug;
+    options.split_debug_path = opt.split_debug.clone();
+    options.demangle = opt.demangle;
+    options.dry_run = opt.dry_run;
+    options.drop_duplicate_objects = opt.drop_duplicate_objects;
+    options.report_path = opt.report.clone();
+    options.dep_graph_path = opt.emit_dep_graph.clone();
+    options.symbol_prefix = opt.prefix_symbols.clone();
+    if let Some(path) = &opt.rename_symbols {
+        options.symbol_renames = parse_rename_file(path)?;
+    }
+    if let Some(path) = &opt.allow_undefined {
+        options.allowed_undefined = Some(parse_pattern_file(path)?);
+    }
+    for policy in &opt.policy {
+        let (library, action, regex) = parse_policy(policy)?;
+        options.library_rules.entry(library).or_default().push(action, regex);
+    }
+
+    let merger = ArMerger::new_from_paths_with_options(&opt.inputs, &opt.output, options)?;
     let object_order = if let Some(path) = &opt.order_file {
         parse_order_file(path)
     } else {
         Vec::new()
     };
 
-    match (opt.keep_symbols.is_empty(), opt.remove_symbols.is_empty()) {
-        (true, true) => {
-            // If we don't need to localize any symbols, this is the easy case where we just extract
-            // contents and re-pack them, no linker necessary.
-            merger.merge_simple()?;
-        },
-        (false, true) => {
-            let keep_symbols: Vec<Regex> = opt
-                .keep_symbols
-                .into_iter()
-                .map(|s| Regex::new(&s))
-                .collect::<Result<Vec<_>, _>>()?;
-            merger.merge_and_localize_ordered(ArmergeKeepOrRemove::KeepSymbols, keep_symbols, object_order)?;
-        },
-        (true, false) => {
-            let remove_symbols: Vec<Regex> = opt
-                .remove_symbols
-                .into_iter()
-                .map(|s| Regex::new(&s))
-                .collect::<Result<Vec<_>, _>>()?;
-            merger.merge_and_localize_ordered(ArmergeKeepOrRemove::RemoveSymbols, remove_symbols, object_order)?;
-        },
-        (false, false) => {
-            return Err("Can't have both keep-symbols and remove-symbols options at the same time".to_string().into());
+    if let Some(path) = &opt.version_script {
+        let version_script = VersionScript::parse(&std::fs::read_to_string(path)?)?;
+        if !opt.why.is_empty() {
+            print_answers(merger.explain_with_version_script(&version_script, &opt.why)?);
+        } else {
+            merger.merge_with_version_script(&version_script, object_order)?;
         }
+        return Ok(());
+    }
+
+    let mut keep_lists = Vec::new();
+    for path in &opt.exported_symbols_list {
+        keep_lists.push(SymbolList::parse_exported_symbols_list(&std::fs::read_to_string(path)?)?);
+    }
+    for path in &opt.def_file {
+        keep_lists.push(SymbolList::parse_def_file(&std::fs::read_to_string(path)?)?);
+    }
+
+    let mut rules = SymbolRules::new();
+    for (action, pattern) in symbol_rules {
+        rules.push(action, Regex::new(&pattern)?);
+    }
+    for regex in keep_lists.into_iter().flat_map(SymbolList::into_regexes) {
+        rules.push(SymbolAction::Keep, regex);
+    }
+
+    if rules.is_empty() && opt.policy.is_empty() {
+        if opt.prefix_symbols.is_some() || opt.rename_symbols.is_some() {
+            return Err("Renaming symbols requires merging objects with keep-symbols, remove-symbols or a version script".to_string().into());
+        }
+        if opt.report.is_some() || opt.emit_dep_graph.is_some() {
+            return Err("Reports and dependency graphs can only be written when localizing symbols with keep-symbols, remove-symbols or a version script".to_string().into());
+        }
+        if opt.allow_undefined.is_some() {
+            return Err("Checking external symbols requires keep-symbols, remove-symbols or a version script".to_string().into());
+        }
+        if opt.strip_debug || opt.split_debug.is_some() {
+            return Err("Stripping debug info requires keep-symbols, remove-symbols or a version script".to_string().into());
+        }
+        if opt.gc_sections {
+            return Err("Collecting unreachable sections requires keep-symbols, remove-symbols or a version script".to_string().into());
+        }
+        if opt.hidden_visibility {
+            return Err("Hiding symbols requires keep-symbols, remove-symbols or a version script".to_string().into());
+        }
+        if opt.demangle {
+            return Err("Matching demangled names requires keep-symbols or remove-symbols".to_string().into());
+        }
+        if opt.drop_duplicate_objects {
+            return Err("Dropping duplicate objects requires keep-symbols, remove-symbols or a version script".to_string().into());
+        }
+        if !opt.why.is_empty() {
+            return Err("Explaining objects and symbols requires keep-symbols, remove-symbols or a version script".to_string().into());
+        }
+        // If we don't need to localize any symbols, this is the easy case where we just extract
+        // contents and re-pack them, no linker necessary.
+        merger.merge_simple()?;
+    } else if !opt.why.is_empty() {
+        print_answers(merger.explain_with_rules(rules, &opt.why)?);
+    } else {
+        merger.merge_with_rules(rules, object_order)?;
     }
 
     Ok(())
 }
 
+fn print_answers(answers: Vec<String>) {
+    for answer in answers {
+        print!("{answer}");
+    }
+}
+
 fn parse_order_file(path: &Path) -> Vec<String> {
     BufReader::new(File::open(path).unwrap())
         .lines()
         .map(|line| line.unwrap().trim().to_string())
         .filter(|line| !line.is_empty() && !line.starts_with('#'))
         .collect()
-}
\ No newline at end of file
+}
+
+/// Parses a `library=action[:regex]` library policy rule
+fn parse_policy(policy: &str) -> Result<(String, SymbolAction, Regex), Box<dyn Error>> {
+    let Some((library, rule)) = policy.split_once('=') else {
+        return Err(format!("Invalid policy {policy}, expected library=keep:regex or library=localize:regex").into());
+    };
+    let (action, pattern) = rule.split_once(':').unwrap_or((rule, ".*"));
+    let action = match action {
+        "keep" => SymbolAction::Keep,
+        "localize" => SymbolAction::Localize,
+        _ => return Err(format!("Invalid action {action} in policy {policy}, expected keep or localize").into()),
+    };
+    Ok((library.to_string(), action, Regex::new(pattern)?))
+}
+
+fn parse_pattern_file(path: &Path) -> Result<Vec<Regex>, Box<dyn Error>> {
+    let mut patterns = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            patterns.push(Regex::new(line)?);
+        }
+    }
+    Ok(patterns)
+}
+
+fn parse_rename_file(path: &Path) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    let mut renames = BTreeMap::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [name, new_name] => {
+                renames.insert(name.to_string(), new_name.to_string());
+            }
+            _ => return Err(format!("Invalid line in rename file {}: {line}", path.display()).into()),
+        }
+    }
+    Ok(renames)
+}
@@ -20,10 +20,24 @@ pub enum MergeError {
     ExternalToolLaunchError { tool: String, inner: io::Error },
     #[error("failed to parse extracted object file at {path}: {inner}")]
     InvalidObject { path: PathBuf, inner: object::Error },
+    #[error("can't merge {name}: {reason}")]
+    UnsupportedObject { name: String, reason: String },
+    #[error("symbol `{name}` is defined in both {first} and {second}{}", hint.as_ref().map(|hint| format!(": {hint}")).unwrap_or_default())]
+    DuplicateSymbol {
+        name: String,
+        first: String,
+        second: String,
+        /// How to resolve the conflict, when the objects tell more about it
+        hint: Option<String>,
+    },
+    #[error("the merged library uses external symbols that aren't allowed: {}", symbols.join(", "))]
+    DisallowedUndefinedSymbols { symbols: Vec<String> },
     #[error("zero objects left after filtering! Make sure to keep at least one public symbol")]
     NoObjectsLeft,
     #[error("failed to write merged output: {0}")]
     WritingArchive(io::Error),
+    #[error("failed to write report: {0}")]
+    WritingReport(io::Error),
     #[error("internal I/O error: {0}")]
     InternalIoError(#[from] io::Error),
     #[error("internal error while merging libraries: {0}")]
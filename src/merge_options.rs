@@ -0,0 +1,67 @@
+use crate::symbol_rules::SymbolRules;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+/// Options controlling how the input libraries are merged
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct MergeOptions {
+    /// Merge objects with the system linker (`$LD -r`) instead of the builtin ELF linker.
+    /// The builtin linker only handles x86_64 and aarch64 ELF objects, other inputs always go through the system linker.
+    /// The system linker is also used when the `LD` or `ARMERGE_LDFLAGS` environment variables are set.
+    pub use_system_linker: bool,
+    /// Write the output as a GNU thin archive, which references the objects in place instead of copying them.
+    /// Objects that only exist inside an input archive are extracted to a `<output>.objects` directory.
+    /// Only archives of ELF objects can be written as thin archives.
+    pub thin_archive: bool,
+    /// Write reproducible archives, with zeroed timestamps, uids and gids in the member headers,
+    /// so that merging the same inputs always produces the same bytes.
+    pub deterministic: bool,
+    /// Prefix added to the name of every global symbol defined in the merged object, and to the references to them.
+    /// On Mach-O, the prefix goes after the leading underscore of C symbols.
+    /// Symbols are only renamed when merging with `merge_and_localize`.
+    pub symbol_prefix: Option<String>,
+    /// New names of global symbols defined in the merged object, which take precedence over `symbol_prefix`
+    pub symbol_renames: BTreeMap<String, String>,
+    /// Match the symbol regexes against demangled C++ and Rust names (e.g. `^mylib::api::`) instead of mangled names.
+    /// Symbols that aren't mangled are matched by their plain name.
+    pub demangle: bool,
+    /// Only decide which objects and symbols are kept, without merging objects or writing the output.
+    pub dry_run: bool,
+    /// Write a JSON report of the objects that are merged or dropped, of the global symbols that are kept or
+    /// localized with the pattern that matched them, and totals per input library.
+    pub report_path: Option<PathBuf>,
+    /// Write the dependency graph of the objects, with edges labelled by the symbols that cause them.
+    /// The graph is written as JSON if the path ends with `.json`, or else in Graphviz (dot) format.
+    pub dep_graph_path: Option<PathBuf>,
+    /// Skip merged objects whose global symbols are all defined by an earlier merged object, because they
+    /// are copies of the same object linked into several input libraries. Without this, any strong global
+    /// symbol defined by two merged objects is reported as a duplicate definition before merging.
+    pub drop_duplicate_objects: bool,
+    /// Patterns of the external symbols the merged objects may use without defining them.
+    /// When set, merging fails if an undefined symbol matches none of them, by its mangled or demangled name.
+    pub allowed_undefined: Option<Vec<Regex>>,
+    /// Keep the objects extracted from the input libraries in memory instead of a temporary directory.
+    /// They are only written to disk when an external tool (the system linker) needs them as files.
+    pub in_memory: bool,
+    /// The rules deciding which global symbols stay global for the objects of the input libraries with these names,
+    /// instead of the rules given when merging. Rules set with `InputLibrary::with_symbol_rules` take precedence.
+    pub library_rules: HashMap<String, SymbolRules>,
+    /// Leave the symbols that are not kept global, but with hidden visibility on ELF (`STV_HIDDEN`)
+    /// or as private externs on Mach-O, instead of localizing them. They can still be linked across
+    /// the objects of a shared library that the merged library is linked into, without being exported from it.
+    /// COFF objects have no hidden visibility.
+    pub hidden_visibility: bool,
+    /// Drop the sections of the merged ELF objects that can't be reached from the symbols that stay global,
+    /// following relocations, instead of keeping whole objects. This is most useful with `-ffunction-sections`
+    /// and `-fdata-sections` objects. Merging then always uses the system linker, which must be GNU ld.
+    pub gc_sections: bool,
+    /// Remove the debug sections of the merged object.
+    /// Debug info is only stripped when merging with `merge_and_localize`.
+    pub strip_debug: bool,
+    /// Write the debug info of the merged object to this file, and remove it from the merged object.
+    /// On ELF, the file only contains the debug sections, and the merged object gets a `.gnu_debuglink` to it.
+    /// On Mach-O, the file is a copy of the merged object with its debug info, that `dsymutil` can read.
+    pub split_debug_path: Option<PathBuf>,
+}
@@ -1,84 +1,253 @@
+mod debug_info;
+mod dep_graph;
+mod duplicates;
 mod filter_deps;
 mod merge;
+mod policy;
+mod rename;
+mod report;
 mod syms;
+mod undefined;
+mod visibility;
+mod why;
 
 #[cfg(feature = "objpoke_symbols")]
 mod builtin_filter;
+mod builtin_link;
+mod coff_filter;
 mod system_filter;
 
 use crate::arbuilder::ArBuilder;
 use crate::archives::get_object_name_from_path;
-use crate::{ArchiveContents, ArmergeKeepOrRemove, MergeError};
+use crate::demangle;
+use crate::objects::filter_deps::RequiredObjects;
+use crate::objects::policy::SymbolPolicy;
+use crate::objects::syms::ObjectSyms;
+use crate::symbol_rules::SymbolRules;
+use crate::{ArchiveContents, MergeError, MergeOptions};
+use rayon::prelude::*;
 use regex::Regex;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tempfile::TempDir;
+use tracing::info;
 
 pub struct ObjectTempDir {
     pub dir: TempDir,
     pub objects: Vec<PathBuf>,
+    /// The original files of the objects that were copied from a thin archive
+    pub origins: HashMap<PathBuf, PathBuf>,
+    /// The names of the objects in the archive they were extracted from
+    pub names: HashMap<PathBuf, String>,
+    /// The input library that each object was extracted from
+    pub libraries: HashMap<PathBuf, String>,
+    /// The symbol rules of the input libraries that have their own, by library name
+    pub library_rules: HashMap<String, SymbolRules>,
+    /// The contents of the objects that were extracted in memory, which are only written to their path
+    /// in the temporary directory when an external tool needs them
+    pub in_memory: HashMap<PathBuf, Arc<[u8]>>,
+}
+
+impl ObjectTempDir {
+    /// The contents of an object, from memory if it was extracted in memory, or else from its file
+    pub fn read(&self, path: &Path) -> std::io::Result<Cow<'_, [u8]>> {
+        match self.in_memory.get(path) {
+            Some(data) => Ok(Cow::Borrowed(data)),
+            None => std::fs::read(path).map(Cow::Owned),
+        }
+    }
+
+    /// Writes the objects that are only in memory to their path, for the external tools that need files
+    pub fn write_to_disk(&self, objects: &[PathBuf]) -> std::io::Result<()> {
+        let pending = objects
+            .iter()
+            .filter_map(|path| Some((path, self.in_memory.get(path)?)))
+            .filter(|(path, _)| !path.exists())
+            .collect::<Vec<_>>();
+        if !pending.is_empty() {
+            info!("Writing {} objects from memory to disk", pending.len());
+        }
+        pending
+            .into_par_iter()
+            .try_for_each(|(path, data)| std::fs::write(path, data))
+    }
 }
 
 pub fn merge_required_objects(
     contents_type: ArchiveContents,
-    obj_dir: &Path,
+    obj_dir: &ObjectTempDir,
     merged_path: &Path,
     objs: &[PathBuf],
-    keep_or_remove: ArmergeKeepOrRemove,
-    regexes: &[Regex],
+    object_syms: &BTreeMap<PathBuf, ObjectSyms>,
+    rules: &SymbolRules,
+    options: &MergeOptions,
 ) -> Result<(), MergeError> {
     #[allow(clippy::if_same_then_else)] // Clippy can't see both [cfg] at once
-    if contents_type == ArchiveContents::Elf {
+    if contents_type == ArchiveContents::Elf && !options.hidden_visibility && !options.gc_sections {
         #[cfg(feature = "objpoke_symbols")]
         builtin_filter::merge_required_objects(
             obj_dir,
             merged_path,
             objs,
-            keep_or_remove,
-            regexes,
+            rules,
+            options,
         )?;
         #[cfg(not(feature = "objpoke_symbols"))]
-        system_filter::merge_required_objects(obj_dir, merged_path, objs, keep_or_remove, regexes)?;
+        system_filter::merge_required_objects(
+            obj_dir,
+            merged_path,
+            objs,
+            object_syms,
+            rules,
+            options,
+        )?;
+    } else if contents_type != ArchiveContents::Elf && options.gc_sections {
+        return Err(MergeError::UnsupportedObject {
+            name: merged_path.display().to_string(),
+            reason: "unreachable sections can only be collected in ELF objects".to_string(),
+        });
+    } else if contents_type == ArchiveContents::Coff {
+        if options.hidden_visibility {
+            return Err(MergeError::UnsupportedObject {
+                name: merged_path.display().to_string(),
+                reason: "COFF symbols have no hidden visibility, they can only be localized".to_string(),
+            });
+        }
+        coff_filter::merge_required_objects(obj_dir, merged_path, objs, rules)?;
     } else if contents_type == ArchiveContents::MachO {
         system_filter::merge_required_macho_objects(
             obj_dir,
             merged_path,
             objs,
-            keep_or_remove,
-            regexes,
+            object_syms,
+            rules,
+            options,
         )?;
     } else {
-        system_filter::merge_required_objects(obj_dir, merged_path, objs, keep_or_remove, regexes)?;
+        system_filter::merge_required_objects(
+            obj_dir,
+            merged_path,
+            objs,
+            object_syms,
+            rules,
+            options,
+        )?;
     }
     Ok(())
 }
 
+/// The policy of a list of rules, with the patterns resolved to mangled names if they match demangled names
+fn resolve_policy(
+    objects: &ObjectTempDir,
+    rules: SymbolRules,
+    options: &MergeOptions,
+) -> Result<SymbolPolicy, MergeError> {
+    if !options.demangle {
+        return Ok(SymbolPolicy::new(rules));
+    }
+    let patterns = rules
+        .rules()
+        .iter()
+        .map(|(_, regex)| regex.clone())
+        .collect::<Vec<_>>();
+    let mangled = demangle::mangled_regexes(objects, &patterns)?;
+    let mut policy = SymbolPolicy::new(SymbolRules::new().with_default(rules.default_action()));
+    for ((action, pattern), regex) in rules.rules().iter().zip(mangled) {
+        if let Some(regex) = regex {
+            policy.push(*action, regex, pattern.as_str().to_owned());
+        }
+    }
+    Ok(policy)
+}
+
+/// The unwind and personality symbols, which stay global so the runtime and other objects can resolve them
+pub(crate) fn runtime_symbol_regexes() -> [Regex; 2] {
+    ["^_?_Unwind_.*", "_?__g.._personality_.*"]
+        .map(|regex| Regex::new(regex).expect("Failed to compile Regex"))
+}
+
+/// Decides which objects to merge, and returns the final rules deciding which symbols are localized
+fn select_objects(
+    objects: &ObjectTempDir,
+    rules: SymbolRules,
+    options: &MergeOptions,
+) -> Result<(SymbolPolicy, RequiredObjects), MergeError> {
+    let mut policy = resolve_policy(objects, rules, options)?;
+    for (library, rules) in &objects.library_rules {
+        let library_policy = resolve_policy(objects, rules.clone(), options)?;
+        policy.libraries.insert(library.to_owned(), library_policy);
+    }
+
+    // When filtering symbols to keep just the public API visible,
+    // we must make an exception for the unwind symbols (if linked statically)
+    let [unwind_regex, personality_regex] = runtime_symbol_regexes();
+    policy.keep_in_public_apis(&unwind_regex);
+
+    let required_objects = filter_deps::filter_required_objects(objects, &policy)?;
+
+    // However, some symbols are not indicative of the fact that we need to keep an object file
+    policy.keep_in_public_apis(&personality_regex);
+
+    Ok((policy, required_objects))
+}
+
+/// Explains why each queried object or symbol is merged, dropped, kept or localized, without merging
+pub fn explain(
+    objects: ObjectTempDir,
+    rules: SymbolRules,
+    queries: &[String],
+    options: &MergeOptions,
+) -> Result<Vec<String>, MergeError> {
+    let (policy, required_objects) = select_objects(&objects, rules, options)?;
+    let merged_objects = required_objects.reasons.keys().cloned().collect::<Vec<_>>();
+    let merged_rules =
+        policy.merged_rules(&objects, &required_objects.object_syms, &merged_objects);
+    Ok(queries
+        .iter()
+        .map(|query| {
+            why::explain(
+                &objects,
+                &required_objects,
+                &policy,
+                &merged_rules,
+                options.hidden_visibility,
+                query,
+            )
+        })
+        .collect())
+}
+
 pub fn merge(
     mut output: Box<dyn ArBuilder>,
     contents_type: ArchiveContents,
     objects: ObjectTempDir,
-    keep_or_remove: ArmergeKeepOrRemove,
-    mut regexes: Vec<Regex>,
+    rules: SymbolRules,
     object_order: HashMap<String, usize>,
+    options: &MergeOptions,
 ) -> Result<(), MergeError> {
-    let merged_name = "merged.o";
+    let merged_name = if contents_type == ArchiveContents::Coff {
+        "merged.obj"
+    } else {
+        "merged.o"
+    };
     let mut merged_path = objects.dir.path().to_owned();
     merged_path.push(merged_name);
 
-    if keep_or_remove == ArmergeKeepOrRemove::KeepSymbols {
-        // When filtering symbols to keep just the public API visible,
-        // we must make an exception for the unwind symbols (if linked statically)
-        regexes.push(Regex::new("^_?_Unwind_.*").expect("Failed to compile Regex"));
-    }
-
-    let required_objects =
-        filter_deps::filter_required_objects(&objects.objects, keep_or_remove, &regexes)?;
+    let (policy, mut required_objects) = select_objects(&objects, rules, options)?;
+    // Explaining doesn't check duplicates, it must work on the inputs that fail to merge
+    duplicates::check_duplicate_definitions(
+        &objects.objects,
+        &mut required_objects,
+        options.drop_duplicate_objects,
+    )?;
 
-    if required_objects.is_empty() {
+    if required_objects.reasons.is_empty() {
         return Err(MergeError::NoObjectsLeft);
     }
 
-    let mut sorted_objects = required_objects.into_keys().collect::<Vec<_>>();
+    let mut sorted_objects = required_objects.reasons.keys().cloned().collect::<Vec<_>>();
     sorted_objects.sort_by(|a, b| {
         let name1 = get_object_name_from_path(a);
         let name2 = get_object_name_from_path(b);
@@ -87,21 +256,44 @@ pub fn merge(
         i1.cmp(i2)
     });
 
-    if keep_or_remove == ArmergeKeepOrRemove::KeepSymbols {
-        // When filtering symbols to keep just the public API visible,
-        // we must make an exception for the unwind symbols (if linked statically)
-        // However, some symbols are not indicative of the fact that we need to keep an object file
-        regexes.push(Regex::new("_?__g.._personality_.*").expect("Failed to compile Regex"));
+    let merged_rules = policy.merged_rules(&objects, &required_objects.object_syms, &sorted_objects);
+    let unresolved = undefined::unresolved_symbols(&required_objects);
+    if let Some(report_path) = &options.report_path {
+        report::write_report(
+            report_path,
+            &objects,
+            &required_objects,
+            &unresolved,
+            &policy,
+            &merged_rules,
+            options.hidden_visibility,
+        )?;
+    }
+    if let Some(graph_path) = &options.dep_graph_path {
+        dep_graph::write_dep_graph(graph_path, &objects, &required_objects)?;
+    }
+    if let Some(allowed) = &options.allowed_undefined {
+        undefined::check_allowed(&unresolved, allowed)?;
+    }
+    if options.dry_run {
+        return Ok(());
     }
 
     merge_required_objects(
         contents_type,
-        objects.dir.path(),
+        &objects,
         &merged_path,
         &sorted_objects,
-        keep_or_remove,
-        &regexes,
+        &required_objects.object_syms,
+        &merged_rules,
+        options,
     )?;
+    if rename::has_renames(options) {
+        rename::rename_symbols(objects.dir.path(), &merged_path, options)?;
+    }
+    if debug_info::has_debug_changes(options) {
+        debug_info::strip_debug_info(&merged_path, contents_type, options)?;
+    }
 
     output.append_obj(&merged_path)?;
     output.close()?;
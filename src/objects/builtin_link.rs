@@ -0,0 +1,799 @@
+use crate::archives::get_object_name_from_path;
+use crate::demangle;
+use crate::MergeError;
+use object::elf;
+use object::read::elf::{CompressionHeader, ElfFile64, FileHeader, Rela, SectionHeader, Sym};
+use object::write::elf::{
+    FileHeader as OutFileHeader, Rel, SectionHeader as OutSectionHeader,
+    SectionIndex as OutSectionIndex, Sym as OutSym, Writer,
+};
+use object::write::StringId;
+use object::{Endianness, Object, ObjectSection, SymbolIndex};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// An input object that the builtin linker knows how to handle
+pub struct InputObject<'data> {
+    path: &'data Path,
+    file: ElfFile64<'data, Endianness>,
+}
+
+/// Where an input section ended up in the output
+#[derive(Copy, Clone, Debug)]
+struct Placement {
+    out: usize,
+    offset: u64,
+}
+
+/// What an input symbol index refers to in the output
+#[derive(Copy, Clone, Debug)]
+enum SymbolTarget {
+    /// The null symbol
+    Null,
+    /// A local symbol defined in a section we dropped, nothing can refer to it in the output
+    Dropped,
+    /// A section symbol, relocations against it must add the offset of its input section
+    Section(Placement),
+    /// A local symbol, with its output symbol index
+    Local(u32),
+    /// A global symbol, with its index in the resolved globals
+    Global(usize),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum SymbolSection {
+    Output(usize),
+    Special(u16),
+}
+
+struct OutputSymbol<'data> {
+    name: &'data [u8],
+    section: SymbolSection,
+    st_info: u8,
+    st_other: u8,
+    st_value: u64,
+    st_size: u64,
+}
+
+/// Definitions that can be overridden sort lower, a strong definition wins over everything else
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum DefinitionStrength {
+    Weak,
+    Common,
+    Strong,
+}
+
+struct GlobalDefinition {
+    input: usize,
+    strength: DefinitionStrength,
+    section: SymbolSection,
+    st_bind: u8,
+    st_type: u8,
+    st_value: u64,
+    st_size: u64,
+}
+
+struct GlobalSymbol<'data> {
+    name: &'data [u8],
+    definition: Option<GlobalDefinition>,
+    ref_type: u8,
+    has_strong_ref: bool,
+    visibility: u8,
+    other_flags: u8,
+}
+
+struct OutputGroup {
+    flags: u32,
+    signature: (usize, SymbolIndex),
+    members: Vec<usize>,
+}
+
+struct OutputSection<'data> {
+    name: &'data [u8],
+    sh_type: u32,
+    sh_flags: u64,
+    sh_entsize: u64,
+    link: Option<usize>,
+    align: u64,
+    size: u64,
+    chunks: Vec<(u64, Cow<'data, [u8]>)>,
+    relocs: Vec<Rel>,
+    group: Option<OutputGroup>,
+    symbol: Option<u32>,
+}
+
+/// Sections with the same key are concatenated in the output
+#[derive(Hash, Eq, PartialEq)]
+struct SectionKey<'data> {
+    name: &'data [u8],
+    sh_type: u32,
+    sh_flags: u64,
+    sh_entsize: u64,
+    link: Option<usize>,
+}
+
+// LLVM-specific sections (address significance tables, call graph profiles, ...) embed symbol
+// indices in their contents. They are optional, so we drop them instead of rewriting them.
+const SHT_LLVM_FIRST: u32 = 0x6fff4c00;
+const SHT_LLVM_LAST: u32 = 0x6fff4cff;
+
+const GNU_PROPERTY_SECTION: &[u8] = b".note.gnu.property";
+
+/// Parses the input objects, or returns the reason why the builtin linker can't handle them
+pub fn parse_inputs<'data>(
+    objects: &'data [PathBuf],
+    data: &'data [impl AsRef<[u8]>],
+) -> Result<Vec<InputObject<'data>>, String> {
+    let mut machine = None;
+    objects
+        .iter()
+        .zip(data)
+        .map(|(path, data)| {
+            let name = get_object_name_from_path(path);
+            let file = ElfFile64::<Endianness>::parse(data.as_ref())
+                .map_err(|e| format!("{name} is not a 64bit ELF object ({e})"))?;
+            let endian = file.endian();
+            let header = file.elf_header();
+            if header.e_type(endian) != elf::ET_REL {
+                return Err(format!("{name} is not a relocatable object"));
+            }
+            let e_machine = header.e_machine(endian);
+            if e_machine != elf::EM_X86_64 && e_machine != elf::EM_AARCH64 {
+                return Err(format!("{name} has unsupported machine type {e_machine}"));
+            }
+            if *machine.get_or_insert((e_machine, endian)) != (e_machine, endian) {
+                return Err(format!("{name} has a different machine type than other objects"));
+            }
+            let sections = file.elf_section_table();
+            for section in sections.iter() {
+                let sh_type = section.sh_type(endian);
+                if sh_type == elf::SHT_REL || sh_type == elf::SHT_CREL {
+                    return Err(format!("{name} uses unsupported relocation section types"));
+                }
+                // The feature bits of GNU property notes must be merged, not concatenated
+                if sh_type == elf::SHT_NOTE
+                    && sections.section_name(endian, section).ok() == Some(GNU_PROPERTY_SECTION)
+                {
+                    return Err(format!("{name} has GNU property notes"));
+                }
+            }
+            Ok(InputObject { path, file })
+        })
+        .collect()
+}
+
+/// Creates a relocatable object out of the inputs, like `ld -r` would.
+///
+/// Sections with the same name and attributes are concatenated, except for section group members
+/// that are kept as-is. Duplicate COMDAT groups are not folded, this is left to the final link.
+pub fn link(merged_path: &Path, inputs: &[InputObject], silent: bool) -> Result<(), MergeError> {
+    if silent {
+        debug!("Merging {} objects with the builtin linker", inputs.len());
+    } else {
+        info!("Merging {} objects with the builtin linker", inputs.len());
+    }
+
+    let mut outputs = Vec::new();
+    let placements = place_sections(inputs, &mut outputs)?;
+    let (symbols, symbol_maps, num_local) = resolve_symbols(inputs, &placements, &mut outputs)?;
+    add_relocations(inputs, &placements, &symbol_maps, num_local, &mut outputs)?;
+
+    let data = write_object(inputs, &outputs, &symbols, &symbol_maps, num_local)?;
+    std::fs::write(merged_path, data)?;
+    Ok(())
+}
+
+fn invalid_object(path: &Path) -> impl Fn(object::Error) -> MergeError + '_ {
+    |inner| MergeError::InvalidObject {
+        path: path.to_owned(),
+        inner,
+    }
+}
+
+fn place_sections<'data>(
+    inputs: &[InputObject<'data>],
+    outputs: &mut Vec<OutputSection<'data>>,
+) -> Result<Vec<Vec<Option<Placement>>>, MergeError> {
+    let mut merge_keys = HashMap::new();
+    let mut placements = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let endian = input.file.endian();
+        let data = input.file.data();
+        let sections = input.file.elf_section_table();
+        let mut placement = vec![None; sections.len()];
+
+        // Sections ordered after another section must be placed after their target
+        let is_link_order =
+            |s: &elf::SectionHeader64<Endianness>| s.sh_flags(endian) & elf::SHF_LINK_ORDER as u64 != 0;
+        let ordered_sections = sections
+            .enumerate()
+            .filter(|(_, s)| !is_link_order(s))
+            .chain(sections.enumerate().filter(|(_, s)| is_link_order(s)));
+
+        for (index, section) in ordered_sections {
+            let sh_type = section.sh_type(endian);
+            let sh_flags = section.sh_flags(endian);
+            match sh_type {
+                elf::SHT_NULL
+                | elf::SHT_SYMTAB
+                | elf::SHT_STRTAB
+                | elf::SHT_RELA
+                | elf::SHT_SYMTAB_SHNDX => continue,
+                SHT_LLVM_FIRST..=SHT_LLVM_LAST => {
+                    debug!(
+                        "Dropping LLVM section of type {:#x} in {}",
+                        sh_type,
+                        input.path.display()
+                    );
+                    continue;
+                }
+                _ => {}
+            }
+
+            let name = sections
+                .section_name(endian, section)
+                .map_err(invalid_object(input.path))?;
+            let link = if sh_flags & elf::SHF_LINK_ORDER as u64 != 0 {
+                placement
+                    .get(section.sh_link(endian) as usize)
+                    .copied()
+                    .flatten()
+                    .map(|p: Placement| p.out)
+            } else {
+                None
+            };
+            let out_flags = sh_flags & !(elf::SHF_COMPRESSED as u64);
+            let new_output = || OutputSection {
+                name,
+                sh_type,
+                sh_flags: out_flags,
+                sh_entsize: section.sh_entsize(endian),
+                link,
+                align: 1,
+                size: 0,
+                chunks: Vec::new(),
+                relocs: Vec::new(),
+                group: None,
+                symbol: None,
+            };
+            let out = if sh_type == elf::SHT_GROUP || sh_flags & elf::SHF_GROUP as u64 != 0 {
+                outputs.push(new_output());
+                outputs.len() - 1
+            } else {
+                let key = SectionKey {
+                    name,
+                    sh_type,
+                    sh_flags: out_flags,
+                    sh_entsize: section.sh_entsize(endian),
+                    link,
+                };
+                *merge_keys.entry(key).or_insert_with(|| {
+                    outputs.push(new_output());
+                    outputs.len() - 1
+                })
+            };
+
+            let contents = if sh_type == elf::SHT_NOBITS || sh_type == elf::SHT_GROUP {
+                None
+            } else if sh_flags & elf::SHF_COMPRESSED as u64 != 0 {
+                let section = input
+                    .file
+                    .section_by_index(index)
+                    .map_err(invalid_object(input.path))?;
+                Some(section.uncompressed_data().map_err(invalid_object(input.path))?)
+            } else {
+                Some(Cow::Borrowed(
+                    section.data(endian, data).map_err(invalid_object(input.path))?,
+                ))
+            };
+            let size = contents
+                .as_ref()
+                .map(|c| c.len() as u64)
+                .unwrap_or_else(|| section.sh_size(endian));
+
+            let output = &mut outputs[out];
+            // Compressed sections are aligned for their compression header, not for their contents
+            let align = match section
+                .compression(endian, data)
+                .map_err(invalid_object(input.path))?
+            {
+                Some((header, _, _)) => header.ch_addralign(endian),
+                None => section.sh_addralign(endian),
+            };
+            let align = align.max(1).next_power_of_two();
+            let offset = output.size.next_multiple_of(align);
+            output.size = offset + size;
+            output.align = output.align.max(align);
+            if let Some(contents) = contents {
+                output.chunks.push((offset, contents));
+            }
+            placement[index.0] = Some(Placement { out, offset });
+        }
+
+        for (index, section) in sections.enumerate() {
+            let Some((flags, members)) = section.group(endian, data).map_err(invalid_object(input.path))?
+            else {
+                continue;
+            };
+            let Some(group_placement) = placement[index.0] else {
+                continue;
+            };
+            outputs[group_placement.out].group = Some(OutputGroup {
+                flags,
+                signature: (
+                    placements.len(),
+                    SymbolIndex(section.sh_info(endian) as usize),
+                ),
+                members: members
+                    .iter()
+                    .filter_map(|m| placement.get(m.get(endian) as usize).copied().flatten())
+                    .map(|p| p.out)
+                    .collect(),
+            });
+        }
+        placements.push(placement);
+    }
+    Ok(placements)
+}
+
+fn merge_visibility(a: u8, b: u8) -> u8 {
+    // The most constraining visibility wins, STV_DEFAULT (0) is the least constraining
+    match (a, b) {
+        (elf::STV_DEFAULT, v) | (v, elf::STV_DEFAULT) => v,
+        (a, b) => a.min(b),
+    }
+}
+
+type ResolvedSymbols<'data> = (Vec<OutputSymbol<'data>>, Vec<Vec<SymbolTarget>>, u32);
+
+fn resolve_symbols<'data>(
+    inputs: &[InputObject<'data>],
+    placements: &[Vec<Option<Placement>>],
+    outputs: &mut [OutputSection<'data>],
+) -> Result<ResolvedSymbols<'data>, MergeError> {
+    let mut symbols = Vec::new();
+    for (idx, output) in outputs.iter_mut().enumerate() {
+        if output.sh_type == elf::SHT_GROUP {
+            continue;
+        }
+        symbols.push(OutputSymbol {
+            name: &[],
+            section: SymbolSection::Output(idx),
+            st_info: elf::STT_SECTION,
+            st_other: elf::STV_DEFAULT,
+            st_value: 0,
+            st_size: 0,
+        });
+        output.symbol = Some(symbols.len() as u32);
+    }
+
+    let mut symbol_maps = Vec::with_capacity(inputs.len());
+    for (input_idx, input) in inputs.iter().enumerate() {
+        let endian = input.file.endian();
+        let symtab = input.file.elf_symbol_table();
+        let mut map = vec![SymbolTarget::Null; symtab.len()];
+        for (index, sym) in symtab.enumerate() {
+            if index.0 == 0 || sym.st_bind() != elf::STB_LOCAL {
+                continue;
+            }
+            let section = symtab
+                .symbol_section(endian, sym, index)
+                .map_err(invalid_object(input.path))?;
+            let placement = section.and_then(|s| placements[input_idx].get(s.0).copied().flatten());
+            if sym.st_type() == elf::STT_SECTION {
+                map[index.0] = match placement {
+                    Some(placement) => SymbolTarget::Section(placement),
+                    None => SymbolTarget::Dropped,
+                };
+                continue;
+            }
+
+            let (section, st_value) = match (section, placement) {
+                (Some(_), Some(p)) => (SymbolSection::Output(p.out), sym.st_value(endian) + p.offset),
+                (Some(_), None) => {
+                    map[index.0] = SymbolTarget::Dropped;
+                    continue;
+                }
+                (None, _) => (SymbolSection::Special(sym.st_shndx(endian)), sym.st_value(endian)),
+            };
+            symbols.push(OutputSymbol {
+                name: symtab.symbol_name(endian, sym).map_err(invalid_object(input.path))?,
+                section,
+                st_info: sym.st_info(),
+                st_other: sym.st_other(),
+                st_value,
+                st_size: sym.st_size(endian),
+            });
+            map[index.0] = SymbolTarget::Local(symbols.len() as u32);
+        }
+        symbol_maps.push(map);
+    }
+    let num_local = symbols.len() as u32 + 1;
+
+    let mut globals: Vec<GlobalSymbol> = Vec::new();
+    let mut global_indices = HashMap::new();
+    for (input_idx, input) in inputs.iter().enumerate() {
+        let endian = input.file.endian();
+        let symtab = input.file.elf_symbol_table();
+        for (index, sym) in symtab.enumerate() {
+            if index.0 == 0 || sym.st_bind() == elf::STB_LOCAL {
+                continue;
+            }
+            let name = symtab.symbol_name(endian, sym).map_err(invalid_object(input.path))?;
+            let global_idx = *global_indices.entry(name).or_insert_with(|| {
+                globals.push(GlobalSymbol {
+                    name,
+                    definition: None,
+                    ref_type: sym.st_type(),
+                    has_strong_ref: false,
+                    visibility: elf::STV_DEFAULT,
+                    other_flags: 0,
+                });
+                globals.len() - 1
+            });
+            symbol_maps[input_idx][index.0] = SymbolTarget::Global(global_idx);
+            let global = &mut globals[global_idx];
+            global.visibility = merge_visibility(global.visibility, sym.st_visibility());
+            global.other_flags |= sym.st_other() & !0x3;
+
+            let shndx = sym.st_shndx(endian);
+            let section = symtab
+                .symbol_section(endian, sym, index)
+                .map_err(invalid_object(input.path))?;
+            let placement = section.and_then(|s| placements[input_idx].get(s.0).copied().flatten());
+            let (section, st_value) = match (shndx, placement) {
+                (elf::SHN_COMMON | elf::SHN_ABS, _) => {
+                    (SymbolSection::Special(shndx), sym.st_value(endian))
+                }
+                (_, Some(p)) => (SymbolSection::Output(p.out), sym.st_value(endian) + p.offset),
+                (_, None) => {
+                    global.has_strong_ref |= sym.st_bind() != elf::STB_WEAK;
+                    continue;
+                }
+            };
+            let strength = match (shndx, sym.st_bind()) {
+                (elf::SHN_COMMON, _) => DefinitionStrength::Common,
+                (_, elf::STB_WEAK | elf::STB_GNU_UNIQUE) => DefinitionStrength::Weak,
+                _ => DefinitionStrength::Strong,
+            };
+            let definition = GlobalDefinition {
+                input: input_idx,
+                strength,
+                section,
+                st_bind: sym.st_bind(),
+                st_type: sym.st_type(),
+                st_value,
+                st_size: sym.st_size(endian),
+            };
+
+            match &mut global.definition {
+                None => global.definition = Some(definition),
+                Some(prev) if prev.strength == DefinitionStrength::Strong && strength == DefinitionStrength::Strong => {
+                    return Err(MergeError::DuplicateSymbol {
+                        name: demangle::display_name(&String::from_utf8_lossy(name)),
+                        first: get_object_name_from_path(inputs[prev.input].path),
+                        second: get_object_name_from_path(input.path),
+                        hint: None,
+                    });
+                }
+                Some(prev) if prev.strength == DefinitionStrength::Common && strength == DefinitionStrength::Common => {
+                    // Common symbols are merged, the largest size and alignment win
+                    prev.st_size = prev.st_size.max(definition.st_size);
+                    prev.st_value = prev.st_value.max(definition.st_value);
+                }
+                Some(prev) if strength > prev.strength => *prev = definition,
+                Some(_) => {}
+            }
+        }
+    }
+
+    for global in globals {
+        let st_other = global.visibility | global.other_flags;
+        symbols.push(match global.definition {
+            Some(def) => OutputSymbol {
+                name: global.name,
+                section: def.section,
+                st_info: (def.st_bind << 4) | def.st_type,
+                st_other,
+                st_value: def.st_value,
+                st_size: def.st_size,
+            },
+            None => {
+                let bind = if global.has_strong_ref {
+                    elf::STB_GLOBAL
+                } else {
+                    elf::STB_WEAK
+                };
+                OutputSymbol {
+                    name: global.name,
+                    section: SymbolSection::Special(elf::SHN_UNDEF),
+                    st_info: (bind << 4) | global.ref_type,
+                    st_other,
+                    st_value: 0,
+                    st_size: 0,
+                }
+            }
+        });
+    }
+
+    Ok((symbols, symbol_maps, num_local))
+}
+
+/// The output symbol index and the addend offset for an input symbol, if it is still in the output
+fn output_symbol_index(
+    target: SymbolTarget,
+    outputs: &[OutputSection],
+    num_local: u32,
+) -> Option<(u32, u64)> {
+    match target {
+        SymbolTarget::Null => Some((0, 0)),
+        SymbolTarget::Dropped => None,
+        SymbolTarget::Section(p) => outputs[p.out].symbol.map(|idx| (idx, p.offset)),
+        SymbolTarget::Local(idx) => Some((idx, 0)),
+        SymbolTarget::Global(idx) => Some((num_local + idx as u32, 0)),
+    }
+}
+
+/// Maps an input symbol index to the output, or fails if the symbol didn't make it to the output
+fn map_symbol_index(
+    input: &InputObject,
+    symbol_map: &[SymbolTarget],
+    index: usize,
+    outputs: &[OutputSection],
+    num_local: u32,
+) -> Result<(u32, u64), MergeError> {
+    let target = symbol_map.get(index).copied().ok_or_else(|| {
+        MergeError::UnsupportedObject {
+            name: get_object_name_from_path(input.path),
+            reason: format!("symbol index {index} is out of bounds"),
+        }
+    })?;
+    output_symbol_index(target, outputs, num_local).ok_or_else(|| MergeError::UnsupportedObject {
+        name: get_object_name_from_path(input.path),
+        reason: format!("symbol {index} is defined in a section the builtin linker drops"),
+    })
+}
+
+fn add_relocations(
+    inputs: &[InputObject],
+    placements: &[Vec<Option<Placement>>],
+    symbol_maps: &[Vec<SymbolTarget>],
+    num_local: u32,
+    outputs: &mut [OutputSection],
+) -> Result<(), MergeError> {
+    for (input_idx, input) in inputs.iter().enumerate() {
+        let endian = input.file.endian();
+        let data = input.file.data();
+        for section in input.file.elf_section_table().iter() {
+            let Some((relas, _)) = section.rela(endian, data).map_err(invalid_object(input.path))?
+            else {
+                continue;
+            };
+            let target = section.sh_info(endian) as usize;
+            let Some(placement) = placements[input_idx].get(target).copied().flatten() else {
+                continue;
+            };
+
+            let mut relocs = Vec::with_capacity(relas.len());
+            for rela in relas {
+                let sym = rela.r_sym(endian, false) as usize;
+                let (r_sym, addend_offset) =
+                    map_symbol_index(input, &symbol_maps[input_idx], sym, outputs, num_local)?;
+                relocs.push(Rel {
+                    r_offset: rela.r_offset(endian) + placement.offset,
+                    r_sym,
+                    r_type: rela.r_type(endian, false),
+                    r_addend: rela.r_addend(endian) + addend_offset as i64,
+                });
+            }
+            outputs[placement.out].relocs.append(&mut relocs);
+        }
+    }
+    Ok(())
+}
+
+fn write_object(
+    inputs: &[InputObject],
+    outputs: &[OutputSection],
+    symbols: &[OutputSymbol],
+    symbol_maps: &[Vec<SymbolTarget>],
+    num_local: u32,
+) -> Result<Vec<u8>, MergeError> {
+    let first = inputs.first().ok_or(MergeError::NoObjectsLeft)?;
+    let endian = first.file.endian();
+    let header = first.file.elf_header();
+    let rela_names = outputs
+        .iter()
+        .map(|o| [b".rela".as_slice(), o.name].concat())
+        .collect::<Vec<_>>();
+
+    let mut buffer = Vec::new();
+    let mut writer = Writer::new(endian, true, &mut buffer);
+    writer.reserve_file_header();
+
+    let section_names = outputs
+        .iter()
+        .map(|o| writer.add_section_name(o.name))
+        .collect::<Vec<_>>();
+    let section_indices = outputs
+        .iter()
+        .map(|_| writer.reserve_section_index())
+        .collect::<Vec<_>>();
+    let rela_sections: Vec<Option<(StringId, OutSectionIndex)>> = outputs
+        .iter()
+        .zip(&rela_names)
+        .map(|(o, name)| {
+            (!o.relocs.is_empty())
+                .then(|| (writer.add_section_name(name), writer.reserve_section_index()))
+        })
+        .collect();
+    let symtab_index = writer.reserve_symtab_section_index();
+    writer.reserve_strtab_section_index();
+    writer.reserve_shstrtab_section_index();
+
+    writer.reserve_null_symbol_index();
+    let symbol_names = symbols
+        .iter()
+        .map(|sym| {
+            let section = match sym.section {
+                SymbolSection::Output(idx) => Some(section_indices[idx]),
+                SymbolSection::Special(_) => None,
+            };
+            writer.reserve_symbol_index(section);
+            (!sym.name.is_empty()).then(|| writer.add_string(sym.name))
+        })
+        .collect::<Vec<_>>();
+    if writer.symtab_shndx_needed() {
+        writer.reserve_symtab_shndx_section_index();
+    }
+
+    let group_entries = |output: &OutputSection| -> Vec<u32> {
+        output.group.iter().flat_map(|g| g.members.iter()).flat_map(|&m| {
+            std::iter::once(section_indices[m].0)
+                .chain(rela_sections[m].map(|(_, idx)| idx.0))
+        }).collect()
+    };
+    let section_offsets = outputs
+        .iter()
+        .map(|o| match o.sh_type {
+            elf::SHT_NOBITS => writer.reserved_len(),
+            elf::SHT_GROUP => writer.reserve((group_entries(o).len() + 1) * 4, 4),
+            _ => writer.reserve(o.size as usize, o.align as usize),
+        })
+        .collect::<Vec<_>>();
+    let rela_offsets = outputs
+        .iter()
+        .map(|o| writer.reserve_relocations(o.relocs.len(), true))
+        .collect::<Vec<_>>();
+    writer.reserve_symtab();
+    writer.reserve_symtab_shndx();
+    writer.reserve_strtab();
+    writer.reserve_shstrtab();
+    writer.reserve_section_headers();
+
+    writer
+        .write_file_header(&OutFileHeader {
+            os_abi: header.e_ident().os_abi,
+            abi_version: header.e_ident().abi_version,
+            e_type: elf::ET_REL,
+            e_machine: header.e_machine(endian),
+            e_entry: 0,
+            e_flags: header.e_flags(endian),
+        })
+        .map_err(|e| MergeError::InternalError(e.into()))?;
+
+    for (output, &offset) in outputs.iter().zip(&section_offsets) {
+        match output.sh_type {
+            elf::SHT_NOBITS => {}
+            elf::SHT_GROUP => {
+                writer.pad_until(offset);
+                let flags = output.group.as_ref().map(|g| g.flags).unwrap_or(0);
+                writer.write(&endian_u32(endian, flags));
+                for entry in group_entries(output) {
+                    writer.write(&endian_u32(endian, entry));
+                }
+            }
+            _ => {
+                for (chunk_offset, chunk) in &output.chunks {
+                    writer.pad_until(offset + *chunk_offset as usize);
+                    writer.write(chunk);
+                }
+                writer.pad_until(offset + output.size as usize);
+            }
+        }
+    }
+    for output in outputs {
+        if output.relocs.is_empty() {
+            continue;
+        }
+        writer.write_align_relocation();
+        for reloc in &output.relocs {
+            writer.write_relocation(true, reloc);
+        }
+    }
+
+    writer.write_null_symbol();
+    for (sym, name) in symbols.iter().zip(&symbol_names) {
+        let (section, st_shndx) = match sym.section {
+            SymbolSection::Output(idx) => (Some(section_indices[idx]), 0),
+            SymbolSection::Special(shndx) => (None, shndx),
+        };
+        writer.write_symbol(&OutSym {
+            name: *name,
+            section,
+            st_info: sym.st_info,
+            st_other: sym.st_other,
+            st_shndx,
+            st_value: sym.st_value,
+            st_size: sym.st_size,
+        });
+    }
+    writer.write_symtab_shndx();
+    writer.write_strtab();
+    writer.write_shstrtab();
+
+    writer.write_null_section_header();
+    for (idx, output) in outputs.iter().enumerate() {
+        let (sh_link, sh_info, sh_size, sh_addralign, sh_entsize) = match &output.group {
+            Some(group) => {
+                let (input, sym) = group.signature;
+                let (signature, _) =
+                    map_symbol_index(&inputs[input], &symbol_maps[input], sym.0, outputs, num_local)?;
+                let size = (group_entries(output).len() as u64 + 1) * 4;
+                (symtab_index.0, signature, size, 4, 4)
+            }
+            None => (
+                output.link.map(|l| section_indices[l].0).unwrap_or(0),
+                0,
+                output.size,
+                output.align,
+                output.sh_entsize,
+            ),
+        };
+        writer.write_section_header(&OutSectionHeader {
+            name: Some(section_names[idx]),
+            sh_type: output.sh_type,
+            sh_flags: output.sh_flags,
+            sh_addr: 0,
+            sh_offset: section_offsets[idx] as u64,
+            sh_size,
+            sh_link,
+            sh_info,
+            sh_addralign,
+            sh_entsize,
+        });
+    }
+    for (idx, output) in outputs.iter().enumerate() {
+        let Some((name, _)) = rela_sections[idx] else {
+            continue;
+        };
+        let group_flag = output.sh_flags & elf::SHF_GROUP as u64;
+        writer.write_section_header(&OutSectionHeader {
+            name: Some(name),
+            sh_type: elf::SHT_RELA,
+            sh_flags: elf::SHF_INFO_LINK as u64 | group_flag,
+            sh_addr: 0,
+            sh_offset: rela_offsets[idx] as u64,
+            sh_size: (output.relocs.len() * size_of::<elf::Rela64<Endianness>>()) as u64,
+            sh_link: symtab_index.0,
+            sh_info: section_indices[idx].0,
+            sh_addralign: 8,
+            sh_entsize: size_of::<elf::Rela64<Endianness>>() as u64,
+        });
+    }
+    writer.write_symtab_section_header(num_local);
+    writer.write_strtab_section_header();
+    writer.write_shstrtab_section_header();
+    writer.write_symtab_shndx_section_header();
+
+    Ok(buffer)
+}
+
+fn endian_u32(endian: Endianness, value: u32) -> [u8; 4] {
+    match endian {
+        Endianness::Little => value.to_le_bytes(),
+        Endianness::Big => value.to_be_bytes(),
+    }
+}
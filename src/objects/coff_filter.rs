@@ -0,0 +1,1061 @@
+use crate::archives::get_object_name_from_path;
+use crate::objects::ObjectTempDir;
+use crate::symbol_rules::{SymbolAction, SymbolRules};
+use crate::MergeError;
+use object::pe;
+use object::pod::bytes_of;
+use object::read::coff::{CoffFile, CoffHeader, ImageSymbol};
+use object::{
+    FileKind, I32Bytes, LittleEndian as LE, Object, ObjectSection, U16Bytes, U32Bytes, U16, U32,
+};
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+const FEAT_SYMBOL_NAME: &[u8] = b"@feat.00";
+const DIRECTIVES_SECTION_NAME: &[u8] = b".drectve";
+/// These sections refer to symbols by their index, which doesn't survive renumbering the symbols
+const DROPPED_SECTION_NAMES: &[&[u8]] = &[b".llvm_addrsig", b".llvm.call-graph-profile"];
+/// Regular COFF section numbers are 16bit, and the values above this one are reserved
+const MAX_REGULAR_SECTIONS: usize = 0xfeff;
+
+struct InputSection<'data> {
+    name: &'data [u8],
+    characteristics: u32,
+    size: u32,
+    data: &'data [u8],
+    relocations: &'data [pe::ImageRelocation],
+}
+
+enum InputAux<'data> {
+    None,
+    Section(&'data pe::ImageAuxSymbolSection),
+    Weak(&'data pe::ImageAuxSymbolWeak),
+    Raw(Vec<&'data pe::ImageSymbolBytes>),
+}
+
+struct InputSymbol<'data> {
+    index: usize,
+    name: &'data [u8],
+    value: u32,
+    section_number: i32,
+    typ: u16,
+    storage_class: u8,
+    aux: InputAux<'data>,
+}
+
+struct InputObject<'data> {
+    path: &'data Path,
+    machine: u16,
+    sections: Vec<InputSection<'data>>,
+    symbols: Vec<InputSymbol<'data>>,
+    /// The number of entries in the symbol table, including auxiliary records
+    symbol_table_len: usize,
+}
+
+impl InputObject<'_> {
+    fn section(&self, section_number: i32) -> &InputSection<'_> {
+        &self.sections[section_number as usize - 1]
+    }
+}
+
+/// What an input symbol index refers to in the output
+#[derive(Copy, Clone, Debug)]
+enum SymbolTarget<'data> {
+    /// A symbol we dropped, or one defined in a discarded section
+    Null,
+    /// A symbol that is only ever defined once, with its position in the output symbols
+    Local(usize),
+    /// An external symbol, resolved by name once all the inputs have been processed
+    External(&'data [u8]),
+}
+
+enum OutputAux<'data> {
+    None,
+    Section {
+        check_sum: u32,
+        number: u32,
+        selection: u8,
+    },
+    Weak {
+        default: SymbolTarget<'data>,
+        search: u32,
+    },
+    Raw(Vec<&'data pe::ImageSymbolBytes>),
+}
+
+impl OutputAux<'_> {
+    fn count(&self) -> usize {
+        match self {
+            OutputAux::None => 0,
+            OutputAux::Section { .. } | OutputAux::Weak { .. } => 1,
+            OutputAux::Raw(records) => records.len(),
+        }
+    }
+}
+
+struct OutputSymbol<'data> {
+    name: &'data [u8],
+    value: u32,
+    section_number: i32,
+    typ: u16,
+    storage_class: u8,
+    aux: OutputAux<'data>,
+}
+
+struct OutputSection<'data> {
+    name: &'data [u8],
+    characteristics: u32,
+    size: u32,
+    data: Cow<'data, [u8]>,
+    relocations: Vec<(usize, &'data pe::ImageRelocation)>,
+    /// The position of the section definition symbol, which holds the COMDAT selection
+    definition: Option<usize>,
+    /// The first symbol defined in the section after its definition, the one naming a COMDAT
+    leader: Option<usize>,
+}
+
+#[derive(Default)]
+struct External<'data> {
+    definition: Option<(usize, usize)>,
+    common_size: Option<u32>,
+    weak: Option<(usize, &'data pe::ImageAuxSymbolWeak)>,
+}
+
+struct MergedObject<'data> {
+    machine: u16,
+    sections: Vec<OutputSection<'data>>,
+    symbols: Vec<OutputSymbol<'data>>,
+    symbol_maps: Vec<Vec<SymbolTarget<'data>>>,
+    externals: HashMap<&'data [u8], usize>,
+}
+
+fn invalid_object(path: &Path) -> impl Fn(object::Error) -> MergeError + '_ {
+    |inner| MergeError::InvalidObject {
+        path: path.to_owned(),
+        inner,
+    }
+}
+
+fn unsupported_object(path: &Path, reason: impl Into<String>) -> MergeError {
+    MergeError::UnsupportedObject {
+        name: get_object_name_from_path(path),
+        reason: reason.into(),
+    }
+}
+
+fn parse_input<'data>(
+    path: &'data Path,
+    data: &'data [u8],
+) -> Result<InputObject<'data>, MergeError> {
+    match FileKind::parse(data) {
+        Ok(FileKind::Coff) => parse_coff::<pe::ImageFileHeader>(path, data),
+        Ok(FileKind::CoffBig) => parse_coff::<pe::AnonObjectHeaderBigobj>(path, data),
+        Ok(FileKind::CoffImport) => Err(unsupported_object(
+            path,
+            "import library members can't be merged into an object",
+        )),
+        Ok(_) => Err(unsupported_object(path, "not a COFF object")),
+        Err(e) => Err(invalid_object(path)(e)),
+    }
+}
+
+fn parse_coff<'data, Coff: CoffHeader>(
+    path: &'data Path,
+    data: &'data [u8],
+) -> Result<InputObject<'data>, MergeError> {
+    let file = CoffFile::<&[u8], Coff>::parse(data).map_err(invalid_object(path))?;
+    let sections = file
+        .sections()
+        .map(|section| {
+            let header = section.coff_section();
+            let characteristics = header.characteristics.get(LE);
+            let data = if characteristics & pe::IMAGE_SCN_CNT_UNINITIALIZED_DATA != 0 {
+                &[]
+            } else {
+                section.data().map_err(invalid_object(path))?
+            };
+            Ok(InputSection {
+                name: section.name_bytes().map_err(invalid_object(path))?,
+                characteristics,
+                size: header.size_of_raw_data.get(LE),
+                data,
+                relocations: section.coff_relocations().map_err(invalid_object(path))?,
+            })
+        })
+        .collect::<Result<Vec<_>, MergeError>>()?;
+
+    let table = file.coff_symbol_table();
+    let symbols = table
+        .iter()
+        .map(|(index, symbol)| {
+            let aux_count = symbol.number_of_aux_symbols() as usize;
+            let aux = if aux_count == 0 {
+                InputAux::None
+            } else if symbol.has_aux_section() {
+                InputAux::Section(table.aux_section(index).map_err(invalid_object(path))?)
+            } else if symbol.has_aux_weak_external() {
+                InputAux::Weak(
+                    table
+                        .aux_weak_external(index)
+                        .map_err(invalid_object(path))?,
+                )
+            } else {
+                InputAux::Raw(
+                    (1..=aux_count)
+                        .map(|i| table.get::<pe::ImageSymbolBytes>(index, i))
+                        .collect::<Result<_, _>>()
+                        .map_err(invalid_object(path))?,
+                )
+            };
+            let section_number = symbol.section_number();
+            if section_number > sections.len() as i32 {
+                return Err(unsupported_object(
+                    path,
+                    "symbol with an invalid section number",
+                ));
+            }
+            Ok(InputSymbol {
+                index: index.0,
+                name: symbol.name(table.strings()).map_err(invalid_object(path))?,
+                value: symbol.value(),
+                section_number,
+                typ: symbol.typ(),
+                storage_class: symbol.storage_class(),
+                aux,
+            })
+        })
+        .collect::<Result<Vec<_>, MergeError>>()?;
+
+    Ok(InputObject {
+        path,
+        machine: file.coff_header().machine(),
+        sections,
+        symbols,
+        symbol_table_len: table.len(),
+    })
+}
+
+pub fn merge_required_objects(
+    obj_dir: &ObjectTempDir,
+    merged_path: &Path,
+    objects: &[PathBuf],
+    rules: &SymbolRules,
+) -> Result<(), MergeError> {
+    let data = objects
+        .par_iter()
+        .map(|path| obj_dir.read(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let inputs = objects
+        .iter()
+        .zip(&data)
+        .map(|(path, data)| parse_input(path, data))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    info!("Merging {} COFF objects", inputs.len());
+    let mut merged = link(&inputs)?;
+    localize_symbols(&mut merged, rules);
+
+    std::fs::write(merged_path, write_object(&inputs, &merged)?)?;
+    Ok(())
+}
+
+/// Picks which copy of each COMDAT is kept, the others are discarded along with their
+/// associative sections. Sections we can't keep meaningful are discarded as well.
+fn discard_sections(inputs: &[InputObject]) -> Result<Vec<Vec<bool>>, MergeError> {
+    let mut discarded = inputs
+        .iter()
+        .map(|input| {
+            input
+                .sections
+                .iter()
+                .map(|section| DROPPED_SECTION_NAMES.contains(&section.name))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // The COMDAT is named by the first symbol defined in the section after its definition
+    let mut winners = HashMap::<&[u8], (usize, i32, u8)>::new();
+    for (input_idx, input) in inputs.iter().enumerate() {
+        let mut selections = HashMap::new();
+        for sym in &input.symbols {
+            let section_number = sym.section_number;
+            if section_number <= 0 {
+                continue;
+            }
+            let section = input.section(section_number);
+            if section.characteristics & pe::IMAGE_SCN_LNK_COMDAT == 0 {
+                continue;
+            }
+            if let InputAux::Section(aux) = sym.aux {
+                selections
+                    .entry(section_number)
+                    .or_insert(Some(aux.selection));
+                continue;
+            }
+            let Some(selection) = selections.get_mut(&section_number).and_then(Option::take) else {
+                continue;
+            };
+            if selection == pe::IMAGE_COMDAT_SELECT_ASSOCIATIVE
+                || sym.storage_class != pe::IMAGE_SYM_CLASS_EXTERNAL
+            {
+                continue;
+            }
+
+            let Some(&(prev_input, prev_section, prev_selection)) = winners.get(sym.name) else {
+                winners.insert(sym.name, (input_idx, section_number, selection));
+                continue;
+            };
+            if selection == pe::IMAGE_COMDAT_SELECT_NODUPLICATES
+                || prev_selection == pe::IMAGE_COMDAT_SELECT_NODUPLICATES
+            {
+                return Err(MergeError::DuplicateSymbol {
+                    name: String::from_utf8_lossy(sym.name).into_owned(),
+                    first: get_object_name_from_path(inputs[prev_input].path),
+                    second: get_object_name_from_path(input.path),
+                    hint: None,
+                });
+            }
+            let prev_size = inputs[prev_input].section(prev_section).size;
+            if selection == pe::IMAGE_COMDAT_SELECT_LARGEST && section.size > prev_size {
+                discarded[prev_input][prev_section as usize - 1] = true;
+                winners.insert(sym.name, (input_idx, section_number, selection));
+            } else {
+                discarded[input_idx][section_number as usize - 1] = true;
+            }
+        }
+    }
+
+    loop {
+        let mut changed = false;
+        for (input_idx, input) in inputs.iter().enumerate() {
+            for sym in &input.symbols {
+                let InputAux::Section(aux) = sym.aux else {
+                    continue;
+                };
+                if sym.section_number <= 0 {
+                    continue;
+                }
+                let section_idx = sym.section_number as usize - 1;
+                let parent = section_number_of(aux) as usize;
+                if aux.selection == pe::IMAGE_COMDAT_SELECT_ASSOCIATIVE
+                    && !discarded[input_idx][section_idx]
+                    && parent > 0
+                    && discarded[input_idx].get(parent - 1) == Some(&true)
+                {
+                    discarded[input_idx][section_idx] = true;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    Ok(discarded)
+}
+
+fn section_number_of(aux: &pe::ImageAuxSymbolSection) -> u32 {
+    aux.number.get(LE) as u32 | (aux.high_number.get(LE) as u32) << 16
+}
+
+/// Puts all the input sections and symbols in a single object, like `link /lib` followed by a
+/// relocatable link would. Sections are kept as-is, except for the linker directives which are
+/// concatenated, and the losing copies of COMDATs which are discarded.
+fn link<'data>(inputs: &'data [InputObject<'data>]) -> Result<MergedObject<'data>, MergeError> {
+    let machine = inputs
+        .first()
+        .map(|input| input.machine)
+        .unwrap_or_default();
+    if let Some(input) = inputs.iter().find(|input| input.machine != machine) {
+        return Err(unsupported_object(
+            input.path,
+            "it has a different machine type than other objects",
+        ));
+    }
+    let discarded = discard_sections(inputs)?;
+
+    let mut sections = Vec::<OutputSection>::new();
+    let mut section_maps = Vec::with_capacity(inputs.len());
+    let mut directives = None;
+    for (input_idx, (input, discarded)) in inputs.iter().zip(&discarded).enumerate() {
+        let mut section_map = Vec::with_capacity(input.sections.len());
+        for (section, &discarded) in input.sections.iter().zip(discarded) {
+            if discarded {
+                section_map.push(None);
+                continue;
+            }
+            let relocations = section.relocations.iter().map(|r| (input_idx, r));
+            if section.name == DIRECTIVES_SECTION_NAME {
+                if let Some(out_idx) = directives {
+                    let out: &mut OutputSection = &mut sections[out_idx];
+                    let data = out.data.to_mut();
+                    data.push(b' ');
+                    data.extend_from_slice(section.data);
+                    out.size = data.len() as u32;
+                    out.relocations.extend(relocations);
+                    section_map.push(Some(out_idx));
+                    continue;
+                }
+                directives = Some(sections.len());
+            }
+            section_map.push(Some(sections.len()));
+            sections.push(OutputSection {
+                name: section.name,
+                characteristics: section.characteristics & !pe::IMAGE_SCN_LNK_NRELOC_OVFL,
+                size: section.size,
+                data: Cow::Borrowed(section.data),
+                relocations: relocations.collect(),
+                definition: None,
+                leader: None,
+            });
+        }
+        section_maps.push(section_map);
+    }
+
+    // Objects built with /SAFESEH or /guard:cf advertise it with @feat.00 flags,
+    // which only remain true for the merged object if all the inputs had them
+    let mut symbols = Vec::new();
+    let feats = inputs
+        .iter()
+        .map(|input| {
+            input
+                .symbols
+                .iter()
+                .find(|sym| is_feat_symbol(sym))
+                .map(|sym| sym.value)
+        })
+        .collect::<Vec<_>>();
+    if feats.iter().any(Option::is_some) {
+        symbols.push(OutputSymbol {
+            name: FEAT_SYMBOL_NAME,
+            value: feats.iter().fold(u32::MAX, |a, b| a & b.unwrap_or(0)),
+            section_number: pe::IMAGE_SYM_ABSOLUTE,
+            typ: 0,
+            storage_class: pe::IMAGE_SYM_CLASS_STATIC,
+            aux: OutputAux::None,
+        });
+    }
+
+    let mut externals = HashMap::<&[u8], External>::new();
+    let mut external_order = Vec::new();
+    let mut symbol_maps = Vec::with_capacity(inputs.len());
+    for (input_idx, input) in inputs.iter().enumerate() {
+        let section_map = &section_maps[input_idx];
+        let mut symbol_map = vec![SymbolTarget::Null; input.symbol_table_len];
+        for sym in &input.symbols {
+            let out_section = usize::try_from(sym.section_number - 1)
+                .ok()
+                .map(|idx| section_map[idx]);
+            if out_section == Some(None) || sym.storage_class == pe::IMAGE_SYM_CLASS_FILE {
+                // In a discarded section, any external we drop here is defined by the winning copy
+                if sym.storage_class == pe::IMAGE_SYM_CLASS_EXTERNAL {
+                    symbol_map[sym.index] = SymbolTarget::External(sym.name);
+                }
+                continue;
+            }
+            if is_feat_symbol(sym) {
+                continue;
+            }
+            let out_section_number = out_section.flatten().map(|idx| idx as i32 + 1);
+            let output_symbol = |aux| OutputSymbol {
+                name: sym.name,
+                value: sym.value,
+                section_number: out_section_number.unwrap_or(sym.section_number),
+                typ: sym.typ,
+                storage_class: sym.storage_class,
+                aux,
+            };
+
+            if let (InputAux::Section(aux), Some(Some(out_idx))) = (&sym.aux, out_section) {
+                let out = &mut sections[out_idx];
+                if let Some(definition) = out.definition {
+                    // The concatenated linker directives only need one definition
+                    symbol_map[sym.index] = SymbolTarget::Local(definition);
+                    continue;
+                }
+                let number = match section_number_of(aux) {
+                    0 => 0,
+                    parent => section_map
+                        .get(parent as usize - 1)
+                        .copied()
+                        .flatten()
+                        .map_or(0, |idx| idx as u32 + 1),
+                };
+                out.definition = Some(symbols.len());
+                symbol_map[sym.index] = SymbolTarget::Local(symbols.len());
+                symbols.push(output_symbol(OutputAux::Section {
+                    check_sum: aux.check_sum.get(LE),
+                    number,
+                    selection: aux.selection,
+                }));
+                continue;
+            }
+
+            let is_defined = sym.section_number > 0 || sym.section_number == pe::IMAGE_SYM_ABSOLUTE;
+            match sym.storage_class {
+                pe::IMAGE_SYM_CLASS_EXTERNAL => {
+                    let external = externals.entry(sym.name).or_insert_with(|| {
+                        external_order.push(sym.name);
+                        External::default()
+                    });
+                    symbol_map[sym.index] = SymbolTarget::External(sym.name);
+                    if is_defined {
+                        if let Some((_, prev_input)) = external.definition {
+                            return Err(MergeError::DuplicateSymbol {
+                                name: String::from_utf8_lossy(sym.name).into_owned(),
+                                first: get_object_name_from_path(inputs[prev_input].path),
+                                second: get_object_name_from_path(input.path),
+                                hint: None,
+                            });
+                        }
+                        external.definition = Some((symbols.len(), input_idx));
+                    } else {
+                        if sym.value != 0 && sym.section_number == pe::IMAGE_SYM_UNDEFINED {
+                            let size = external.common_size.get_or_insert(0);
+                            *size = (*size).max(sym.value);
+                        }
+                        continue;
+                    }
+                }
+                pe::IMAGE_SYM_CLASS_WEAK_EXTERNAL if sym.section_number == 0 => {
+                    let external = externals.entry(sym.name).or_insert_with(|| {
+                        external_order.push(sym.name);
+                        External::default()
+                    });
+                    if let InputAux::Weak(aux) = sym.aux {
+                        external.weak.get_or_insert((input_idx, aux));
+                    }
+                    symbol_map[sym.index] = SymbolTarget::External(sym.name);
+                    continue;
+                }
+                _ => symbol_map[sym.index] = SymbolTarget::Local(symbols.len()),
+            }
+
+            if let Some(out) = out_section.flatten().map(|idx| &mut sections[idx]) {
+                if out.definition.is_some() && out.leader.is_none() {
+                    out.leader = Some(symbols.len());
+                }
+            }
+            let aux = match &sym.aux {
+                InputAux::Raw(records) => OutputAux::Raw(records.clone()),
+                _ => OutputAux::None,
+            };
+            symbols.push(output_symbol(aux));
+        }
+        symbol_maps.push(symbol_map);
+    }
+
+    // Whatever is left is not defined by any input
+    let mut external_positions = HashMap::with_capacity(external_order.len());
+    for name in external_order {
+        let external = &externals[name];
+        let pos = if let Some((pos, _)) = external.definition {
+            pos
+        } else {
+            let (storage_class, value, aux) = match (external.common_size, external.weak) {
+                (Some(size), _) => (pe::IMAGE_SYM_CLASS_EXTERNAL, size, OutputAux::None),
+                (None, Some((input_idx, aux))) => (
+                    pe::IMAGE_SYM_CLASS_WEAK_EXTERNAL,
+                    0,
+                    OutputAux::Weak {
+                        default: symbol_maps[input_idx]
+                            .get(aux.default_symbol().0)
+                            .copied()
+                            .unwrap_or(SymbolTarget::Null),
+                        search: aux.weak_search_type.get(LE),
+                    },
+                ),
+                (None, None) => (pe::IMAGE_SYM_CLASS_EXTERNAL, 0, OutputAux::None),
+            };
+            symbols.push(OutputSymbol {
+                name,
+                value,
+                section_number: pe::IMAGE_SYM_UNDEFINED,
+                typ: 0,
+                storage_class,
+                aux,
+            });
+            symbols.len() - 1
+        };
+        external_positions.insert(name, pos);
+    }
+
+    Ok(MergedObject {
+        machine,
+        sections,
+        symbols,
+        symbol_maps,
+        externals: external_positions,
+    })
+}
+
+fn is_feat_symbol(sym: &InputSymbol) -> bool {
+    sym.name == FEAT_SYMBOL_NAME
+        && sym.storage_class == pe::IMAGE_SYM_CLASS_STATIC
+        && sym.section_number == pe::IMAGE_SYM_ABSOLUTE
+}
+
+impl MergedObject<'_> {
+    fn resolve(&self, target: SymbolTarget) -> Option<usize> {
+        match target {
+            SymbolTarget::Null => None,
+            SymbolTarget::Local(pos) => Some(pos),
+            SymbolTarget::External(name) => self.externals.get(name).copied(),
+        }
+    }
+}
+
+/// Makes the defined externals that don't pass the filter static
+fn localize_symbols(merged: &mut MergedObject, rules: &SymbolRules) {
+    let mut localized = vec![false; merged.symbols.len()];
+    let mut kept_count = 0;
+    for (sym, localized) in merged.symbols.iter_mut().zip(&mut localized) {
+        // Common symbols don't have a section to become local in, they always stay global
+        if sym.storage_class != pe::IMAGE_SYM_CLASS_EXTERNAL
+            || sym.section_number == pe::IMAGE_SYM_UNDEFINED
+        {
+            continue;
+        }
+        let name = String::from_utf8_lossy(sym.name);
+        if rules.action(&name) == SymbolAction::Keep {
+            kept_count += 1;
+        } else {
+            sym.storage_class = pe::IMAGE_SYM_CLASS_STATIC;
+            *localized = true;
+        }
+    }
+    info!(
+        "Localizing {} symbols, keeping {} globals",
+        localized.iter().filter(|&&l| l).count(),
+        kept_count
+    );
+
+    let localized_names = merged
+        .symbols
+        .iter()
+        .zip(&localized)
+        .filter(|(_, &localized)| localized)
+        .map(|(sym, _)| sym.name)
+        .collect::<HashSet<_>>();
+    let machine = merged.machine;
+    if let Some(directives) = merged
+        .sections
+        .iter_mut()
+        .find(|section| section.name == DIRECTIVES_SECTION_NAME)
+    {
+        if let Some(filtered) = drop_exports(&directives.data, &localized_names, machine) {
+            directives.size = filtered.len() as u32;
+            directives.data = Cow::Owned(filtered);
+        }
+    }
+
+    // If a symbol we localize names a COMDAT, it has to become a regular section. Otherwise the
+    // local symbol is not really local, because the section could later get folded with other
+    // (potentially incompatible) definitions of the same name. Its associative sections follow.
+    let mut demoted = merged
+        .sections
+        .iter()
+        .map(|section| {
+            section.characteristics & pe::IMAGE_SCN_LNK_COMDAT != 0
+                && section.leader.is_some_and(|leader| localized[leader])
+        })
+        .collect::<Vec<_>>();
+    loop {
+        let mut changed = false;
+        for (idx, section) in merged.sections.iter().enumerate() {
+            let Some(definition) = section.definition else {
+                continue;
+            };
+            if let OutputAux::Section {
+                number, selection, ..
+            } = merged.symbols[definition].aux
+            {
+                if selection == pe::IMAGE_COMDAT_SELECT_ASSOCIATIVE
+                    && !demoted[idx]
+                    && number > 0
+                    && demoted[number as usize - 1]
+                {
+                    demoted[idx] = true;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for (section, _) in merged.sections.iter_mut().zip(&demoted).filter(|(_, &d)| d) {
+        section.characteristics &= !pe::IMAGE_SCN_LNK_COMDAT;
+        if let Some(definition) = section.definition {
+            if let OutputAux::Section {
+                number, selection, ..
+            } = &mut merged.symbols[definition].aux
+            {
+                *number = 0;
+                *selection = 0;
+            }
+        }
+    }
+}
+
+/// Removes the `/EXPORT:` linker directives of these symbols, which the linker would fail to
+/// resolve once they are static. Returns `None` when no directive is removed.
+fn drop_exports(directives: &[u8], names: &HashSet<&[u8]>, machine: u16) -> Option<Vec<u8>> {
+    let mut filtered = Vec::with_capacity(directives.len());
+    let mut dropped = false;
+    let mut rest = directives;
+    while !rest.is_empty() {
+        let start = rest
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let (spaces, tail) = rest.split_at(start);
+        // Arguments can be quoted, quoted spaces don't end them
+        let mut quoted = false;
+        let end = tail
+            .iter()
+            .position(|&c| {
+                quoted ^= c == b'"';
+                c.is_ascii_whitespace() && !quoted
+            })
+            .unwrap_or(tail.len());
+        let (directive, tail) = tail.split_at(end);
+        rest = tail;
+
+        if exported_name(directive).is_some_and(|name| {
+            names.contains(name.as_slice())
+                // i386 exports are written without the leading underscore of C symbols
+                || (machine == pe::IMAGE_FILE_MACHINE_I386
+                    && names.contains([b"_", name.as_slice()].concat().as_slice()))
+        }) {
+            dropped = true;
+            continue;
+        }
+        filtered.extend_from_slice(spaces);
+        filtered.extend_from_slice(directive);
+    }
+    dropped.then_some(filtered)
+}
+
+/// The symbol exported by a `/EXPORT:name[=internal][,options]` directive
+fn exported_name(directive: &[u8]) -> Option<Vec<u8>> {
+    let unquoted = directive
+        .iter()
+        .copied()
+        .filter(|&c| c != b'"')
+        .collect::<Vec<_>>();
+    let prefix = b"/export:".len();
+    if unquoted.len() < prefix || !unquoted[1..prefix].eq_ignore_ascii_case(b"export:") {
+        return None;
+    }
+    if unquoted[0] != b'/' && unquoted[0] != b'-' {
+        return None;
+    }
+    let export = unquoted[prefix..].split(|&c| c == b',').next()?;
+    // Exports renamed with `exported=internal` are defined under their internal name
+    let name = export.rsplit(|&c| c == b'=').next()?;
+    Some(name.to_vec())
+}
+
+#[derive(Default)]
+struct StringTable<'data> {
+    data: Vec<u8>,
+    offsets: HashMap<&'data [u8], u32>,
+}
+
+impl<'data> StringTable<'data> {
+    fn add(&mut self, name: &'data [u8]) -> u32 {
+        if self.data.is_empty() {
+            // The table starts with its own size
+            self.data.extend_from_slice(&[0; 4]);
+        }
+        *self.offsets.entry(name).or_insert_with(|| {
+            let offset = self.data.len() as u32;
+            self.data.extend_from_slice(name);
+            self.data.push(0);
+            offset
+        })
+    }
+
+    fn section_name(&mut self, name: &'data [u8]) -> [u8; 8] {
+        let mut raw_name = [0; 8];
+        if name.len() <= 8 {
+            raw_name[..name.len()].copy_from_slice(name);
+            return raw_name;
+        }
+        let offset = self.add(name);
+        let encoded = if offset <= 9_999_999 {
+            format!("/{offset}").into_bytes()
+        } else {
+            // Offsets that don't fit in 7 decimal digits are written as `//` and 6 base64 digits
+            const BASE64: &[u8] =
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+            let mut encoded = b"//".to_vec();
+            encoded.extend(
+                (0..6)
+                    .rev()
+                    .map(|i| BASE64[(offset as usize >> (6 * i)) & 63]),
+            );
+            encoded
+        };
+        raw_name[..encoded.len()].copy_from_slice(&encoded);
+        raw_name
+    }
+
+    fn symbol_name(&mut self, name: &'data [u8]) -> [u8; 8] {
+        let mut raw_name = [0; 8];
+        if name.len() <= 8 {
+            raw_name[..name.len()].copy_from_slice(name);
+        } else {
+            raw_name[4..].copy_from_slice(&self.add(name).to_le_bytes());
+        }
+        raw_name
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.data.is_empty() {
+            self.data.extend_from_slice(&[0; 4]);
+        }
+        let len = self.data.len() as u32;
+        self.data[..4].copy_from_slice(&len.to_le_bytes());
+        self.data
+    }
+}
+
+fn write_object(inputs: &[InputObject], merged: &MergedObject) -> Result<Vec<u8>, MergeError> {
+    // Switch to the /bigobj format only when the section numbers don't fit in 16bit
+    let bigobj = merged.sections.len() > MAX_REGULAR_SECTIONS;
+    let symbol_size = if bigobj {
+        pe::IMAGE_SIZEOF_SYMBOL_EX
+    } else {
+        pe::IMAGE_SIZEOF_SYMBOL
+    };
+
+    let mut symbol_indices = Vec::with_capacity(merged.symbols.len());
+    let mut symbol_count = 0u32;
+    for sym in &merged.symbols {
+        symbol_indices.push(symbol_count);
+        symbol_count += 1 + sym.aux.count() as u32;
+    }
+
+    let relocations = merged
+        .sections
+        .iter()
+        .map(|section| {
+            section
+                .relocations
+                .iter()
+                .map(|&(input_idx, reloc)| {
+                    let index = reloc.symbol_table_index.get(LE) as usize;
+                    let target = merged.symbol_maps[input_idx]
+                        .get(index)
+                        .copied()
+                        .unwrap_or(SymbolTarget::Null);
+                    let pos = merged.resolve(target).ok_or_else(|| {
+                        unsupported_object(
+                            inputs[input_idx].path,
+                            "relocation against a symbol in a discarded section",
+                        )
+                    })?;
+                    Ok(pe::ImageRelocation {
+                        virtual_address: reloc.virtual_address,
+                        symbol_table_index: U32Bytes::new(LE, symbol_indices[pos]),
+                        typ: reloc.typ,
+                    })
+                })
+                .collect::<Result<Vec<_>, MergeError>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut strings = StringTable::default();
+    let header_size = if bigobj {
+        size_of::<pe::AnonObjectHeaderBigobj>()
+    } else {
+        size_of::<pe::ImageFileHeader>()
+    };
+    let mut offset = header_size + merged.sections.len() * size_of::<pe::ImageSectionHeader>();
+    let mut section_headers = Vec::with_capacity(merged.sections.len());
+    for (section, relocations) in merged.sections.iter().zip(&relocations) {
+        let mut characteristics = section.characteristics;
+        let pointer_to_raw_data = if section.data.is_empty() {
+            0
+        } else {
+            offset = offset.next_multiple_of(4);
+            offset += section.data.len();
+            offset - section.data.len()
+        };
+        // When the count doesn't fit in 16bit, it is stored in an extra first relocation
+        let mut number_of_relocations = relocations.len();
+        if number_of_relocations > u16::MAX as usize {
+            characteristics |= pe::IMAGE_SCN_LNK_NRELOC_OVFL;
+            number_of_relocations += 1;
+        }
+        let pointer_to_relocations = if relocations.is_empty() {
+            0
+        } else {
+            offset += number_of_relocations * size_of::<pe::ImageRelocation>();
+            offset - number_of_relocations * size_of::<pe::ImageRelocation>()
+        };
+        section_headers.push(pe::ImageSectionHeader {
+            name: strings.section_name(section.name),
+            virtual_size: U32::new(LE, 0),
+            virtual_address: U32::new(LE, 0),
+            size_of_raw_data: U32::new(LE, section.size),
+            pointer_to_raw_data: U32::new(LE, pointer_to_raw_data as u32),
+            pointer_to_relocations: U32::new(LE, pointer_to_relocations as u32),
+            pointer_to_linenumbers: U32::new(LE, 0),
+            number_of_relocations: U16::new(
+                LE,
+                number_of_relocations.min(u16::MAX as usize) as u16,
+            ),
+            number_of_linenumbers: U16::new(LE, 0),
+            characteristics: U32::new(LE, characteristics),
+        });
+    }
+    let pointer_to_symbol_table = offset;
+    let end_of_symbols = pointer_to_symbol_table + symbol_count as usize * symbol_size;
+    if u32::try_from(end_of_symbols).is_err() {
+        return Err(MergeError::InternalError(
+            "the merged COFF object would be larger than 4GiB".into(),
+        ));
+    }
+
+    let mut out = Vec::with_capacity(end_of_symbols);
+    if bigobj {
+        let header = pe::AnonObjectHeaderBigobj {
+            sig1: U16::new(LE, pe::IMAGE_FILE_MACHINE_UNKNOWN),
+            sig2: U16::new(LE, 0xffff),
+            version: U16::new(LE, 2),
+            machine: U16::new(LE, merged.machine),
+            time_date_stamp: U32::new(LE, 0),
+            class_id: pe::ANON_OBJECT_HEADER_BIGOBJ_CLASS_ID,
+            size_of_data: U32::new(LE, 0),
+            flags: U32::new(LE, 0),
+            meta_data_size: U32::new(LE, 0),
+            meta_data_offset: U32::new(LE, 0),
+            number_of_sections: U32::new(LE, merged.sections.len() as u32),
+            pointer_to_symbol_table: U32::new(LE, pointer_to_symbol_table as u32),
+            number_of_symbols: U32::new(LE, symbol_count),
+        };
+        out.extend_from_slice(bytes_of(&header));
+    } else {
+        let header = pe::ImageFileHeader {
+            machine: U16::new(LE, merged.machine),
+            number_of_sections: U16::new(LE, merged.sections.len() as u16),
+            time_date_stamp: U32::new(LE, 0),
+            pointer_to_symbol_table: U32::new(LE, pointer_to_symbol_table as u32),
+            number_of_symbols: U32::new(LE, symbol_count),
+            size_of_optional_header: U16::new(LE, 0),
+            characteristics: U16::new(LE, 0),
+        };
+        out.extend_from_slice(bytes_of(&header));
+    }
+    for header in &section_headers {
+        out.extend_from_slice(bytes_of(header));
+    }
+    for ((section, header), relocations) in merged
+        .sections
+        .iter()
+        .zip(&section_headers)
+        .zip(&relocations)
+    {
+        if !section.data.is_empty() {
+            out.resize(header.pointer_to_raw_data.get(LE) as usize, 0);
+            out.extend_from_slice(&section.data);
+        }
+        if header.characteristics.get(LE) & pe::IMAGE_SCN_LNK_NRELOC_OVFL != 0 {
+            let count = pe::ImageRelocation {
+                virtual_address: U32Bytes::new(LE, relocations.len() as u32 + 1),
+                symbol_table_index: U32Bytes::new(LE, 0),
+                typ: U16Bytes::new(LE, 0),
+            };
+            out.extend_from_slice(bytes_of(&count));
+        }
+        for reloc in relocations {
+            out.extend_from_slice(bytes_of(reloc));
+        }
+    }
+
+    let pad_record = |out: &mut Vec<u8>, start: usize| out.resize(start + symbol_size, 0);
+    for sym in &merged.symbols {
+        let start = out.len();
+        let name = strings.symbol_name(sym.name);
+        let aux_count = sym.aux.count() as u8;
+        if bigobj {
+            out.extend_from_slice(bytes_of(&pe::ImageSymbolEx {
+                name,
+                value: U32Bytes::new(LE, sym.value),
+                section_number: I32Bytes::new(LE, sym.section_number),
+                typ: U16Bytes::new(LE, sym.typ),
+                storage_class: sym.storage_class,
+                number_of_aux_symbols: aux_count,
+            }));
+        } else {
+            out.extend_from_slice(bytes_of(&pe::ImageSymbol {
+                name,
+                value: U32Bytes::new(LE, sym.value),
+                section_number: U16Bytes::new(LE, sym.section_number as u16),
+                typ: U16Bytes::new(LE, sym.typ),
+                storage_class: sym.storage_class,
+                number_of_aux_symbols: aux_count,
+            }));
+        }
+
+        match &sym.aux {
+            OutputAux::None => {}
+            &OutputAux::Section {
+                check_sum,
+                number,
+                selection,
+            } => {
+                let idx = sym.section_number as usize - 1;
+                let start = out.len();
+                out.extend_from_slice(bytes_of(&pe::ImageAuxSymbolSection {
+                    length: U32Bytes::new(LE, merged.sections[idx].size),
+                    number_of_relocations: U16Bytes::new(
+                        LE,
+                        relocations[idx].len().min(u16::MAX as usize) as u16,
+                    ),
+                    number_of_linenumbers: U16Bytes::new(LE, 0),
+                    check_sum: U32Bytes::new(LE, check_sum),
+                    number: U16Bytes::new(LE, number as u16),
+                    selection,
+                    reserved: 0,
+                    high_number: U16Bytes::new(LE, (number >> 16) as u16),
+                }));
+                pad_record(&mut out, start);
+            }
+            &OutputAux::Weak { default, search } => {
+                let pos = merged.resolve(default).ok_or_else(|| {
+                    MergeError::InternalError(
+                        format!(
+                            "the default of weak external `{}` was discarded",
+                            String::from_utf8_lossy(sym.name)
+                        )
+                        .into(),
+                    )
+                })?;
+                let start = out.len();
+                out.extend_from_slice(bytes_of(&pe::ImageAuxSymbolWeak {
+                    weak_default_sym_index: U32Bytes::new(LE, symbol_indices[pos]),
+                    weak_search_type: U32Bytes::new(LE, search),
+                }));
+                pad_record(&mut out, start);
+            }
+            OutputAux::Raw(records) => {
+                for record in records {
+                    let start = out.len();
+                    out.extend_from_slice(&record.0);
+                    pad_record(&mut out, start);
+                }
+            }
+        }
+        debug_assert_eq!(out.len(), start + (1 + aux_count as usize) * symbol_size);
+    }
+    out.extend_from_slice(&strings.finish());
+    Ok(out)
+}
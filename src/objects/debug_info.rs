@@ -0,0 +1,57 @@
+use crate::archives::ArchiveContents;
+use crate::objects::system_filter::run_objcopy;
+use crate::{MergeError, MergeOptions};
+use std::ffi::OsString;
+use std::path::Path;
+use tracing::info;
+
+/// Whether the options ask for the debug info of the merged object to be stripped or split
+pub fn has_debug_changes(options: &MergeOptions) -> bool {
+    options.strip_debug || options.split_debug_path.is_some()
+}
+
+/// Removes the debug sections of the merged object. With `split_debug_path`, they are first written to that file:
+/// for ELF, with only the debug sections, and linked back to the merged object with a `.gnu_debuglink` section;
+/// for Mach-O, as a copy of the merged object before stripping, that `dsymutil` can read.
+pub fn strip_debug_info(
+    merged_path: &Path,
+    contents_type: ArchiveContents,
+    options: &MergeOptions,
+) -> Result<(), MergeError> {
+    let mut strip_args = vec![OsString::from("--strip-debug")];
+    if let Some(split_path) = &options.split_debug_path {
+        info!(
+            "Writing the debug info of the merged object to {}",
+            split_path.display()
+        );
+        match contents_type {
+            ArchiveContents::Elf => {
+                run_objcopy(
+                    vec![
+                        OsString::from("--only-keep-debug"),
+                        merged_path.as_os_str().to_owned(),
+                        split_path.as_os_str().to_owned(),
+                    ],
+                    "Failed to split debug info",
+                )?;
+                let mut debuglink = OsString::from("--add-gnu-debuglink=");
+                debuglink.push(split_path);
+                strip_args.push(debuglink);
+            }
+            ArchiveContents::MachO => {
+                std::fs::copy(merged_path, split_path)?;
+            }
+            _ => {
+                return Err(MergeError::UnsupportedObject {
+                    name: merged_path.display().to_string(),
+                    reason: "debug info can only be split from ELF and Mach-O objects".to_string(),
+                })
+            }
+        }
+    } else {
+        info!("Stripping the debug info of the merged object");
+    }
+
+    strip_args.push(merged_path.as_os_str().to_owned());
+    run_objcopy(strip_args, "Failed to strip debug info")
+}
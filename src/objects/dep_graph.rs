@@ -0,0 +1,148 @@
+use crate::archives::get_object_name_from_path;
+use crate::objects::filter_deps::{RequiredObjects, RequiredReason};
+use crate::objects::ObjectTempDir;
+use crate::MergeError;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tracing::info;
+
+/// At most this many symbols are written in the label of an edge of the Graphviz graph
+const MAX_DOT_LABEL_SYMBOLS: usize = 4;
+
+#[derive(Serialize)]
+struct DepGraph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Serialize)]
+struct Node {
+    id: usize,
+    name: String,
+    library: String,
+    /// `root` for objects merged because they define kept symbols,
+    /// `merged` for their dependencies, and `dropped` for the rest
+    status: &'static str,
+}
+
+/// `from` uses `symbols` defined by `to`
+#[derive(Serialize)]
+struct Edge {
+    from: usize,
+    to: usize,
+    symbols: Vec<String>,
+}
+
+/// Writes the dependency graph of the objects, as JSON if the path ends with `.json`, or else in Graphviz format
+pub fn write_dep_graph(
+    graph_path: &Path,
+    objects: &ObjectTempDir,
+    required: &RequiredObjects,
+) -> Result<(), MergeError> {
+    let ids = objects
+        .objects
+        .iter()
+        .enumerate()
+        .map(|(id, path)| (path, id))
+        .collect::<HashMap<_, _>>();
+    let nodes = objects
+        .objects
+        .iter()
+        .enumerate()
+        .map(|(id, path)| Node {
+            id,
+            name: get_object_name_from_path(path),
+            library: objects.libraries.get(path).cloned().unwrap_or_default(),
+            status: match required.reasons.get(path) {
+                Some(RequiredReason::ExportsKeptSymbols) => "root",
+                Some(RequiredReason::RequiredBy { .. }) => "merged",
+                None => "dropped",
+            },
+        })
+        .collect::<Vec<_>>();
+    let mut edges = Vec::new();
+    for (from, path) in objects.objects.iter().enumerate() {
+        let syms = &required.object_syms[path];
+        let mut deps = syms.deps.iter().map(|dep| ids[dep]).collect::<Vec<_>>();
+        deps.sort_unstable();
+        for to in deps {
+            let dep_syms = &required.object_syms[&objects.objects[to]];
+            edges.push(Edge {
+                from,
+                to,
+                symbols: syms
+                    .dependency_symbols(dep_syms)
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect(),
+            });
+        }
+    }
+    let graph = DepGraph { nodes, edges };
+
+    info!(
+        "Writing dependency graph of {} objects to {}",
+        graph.nodes.len(),
+        graph_path.display()
+    );
+    let file = File::create(graph_path).map_err(MergeError::WritingReport)?;
+    let mut out = BufWriter::new(file);
+    if graph_path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::to_writer_pretty(&mut out, &graph)
+            .map_err(|e| MergeError::WritingReport(e.into()))?;
+        out.write_all(b"\n").map_err(MergeError::WritingReport)?;
+    } else {
+        write_dot(&mut out, &graph).map_err(MergeError::WritingReport)?;
+    }
+    out.flush().map_err(MergeError::WritingReport)
+}
+
+fn write_dot(out: &mut impl Write, graph: &DepGraph) -> std::io::Result<()> {
+    writeln!(out, "digraph armerge {{")?;
+    writeln!(out, "    node [shape=box];")?;
+    for node in &graph.nodes {
+        let style = match node.status {
+            "root" => ", style=filled, fillcolor=gold",
+            "merged" => "",
+            _ => ", style=dashed, color=gray, fontcolor=gray",
+        };
+        writeln!(
+            out,
+            "    n{} [label={}{}];",
+            node.id,
+            dot_string(&node.name),
+            style
+        )?;
+    }
+    for edge in &graph.edges {
+        let mut label = edge
+            .symbols
+            .iter()
+            .take(MAX_DOT_LABEL_SYMBOLS)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n");
+        if edge.symbols.len() > MAX_DOT_LABEL_SYMBOLS {
+            label += &format!("\n(+{} more)", edge.symbols.len() - MAX_DOT_LABEL_SYMBOLS);
+        }
+        writeln!(
+            out,
+            "    n{} -> n{} [label={}];",
+            edge.from,
+            edge.to,
+            dot_string(&label)
+        )?;
+    }
+    writeln!(out, "}}")
+}
+
+fn dot_string(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
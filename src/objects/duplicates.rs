@@ -0,0 +1,86 @@
+use crate::archives::get_object_name_from_path;
+use crate::demangle;
+use crate::objects::filter_deps::RequiredObjects;
+use crate::MergeError;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tracing::info;
+
+/// Finds the global symbols that more than one merged object defines, before the linker fails on them.
+/// With `drop_duplicate_objects`, a merged object whose globals are all defined by an earlier merged object
+/// is assumed to be a copy of it, and isn't merged, nor are the objects that only it used.
+pub fn check_duplicate_definitions(
+    objects: &[PathBuf],
+    required: &mut RequiredObjects,
+    drop_duplicate_objects: bool,
+) -> Result<(), MergeError> {
+    loop {
+        let (duplicates, conflict) = find_duplicates(objects, required, drop_duplicate_objects);
+        // Skipping copies can leave out the objects of a conflict, so conflicts are only final without copies
+        if !duplicates.is_empty() {
+            required.remove_objects(&duplicates);
+        } else if let Some(conflict) = conflict {
+            return Err(conflict);
+        } else {
+            return Ok(());
+        }
+    }
+}
+
+/// The merged objects that are copies of an earlier one, and the first conflict between other objects
+fn find_duplicates<'a>(
+    objects: &'a [PathBuf],
+    required: &RequiredObjects,
+    drop_duplicate_objects: bool,
+) -> (Vec<&'a PathBuf>, Option<MergeError>) {
+    let mut definers = HashMap::<&str, &PathBuf>::new();
+    let mut duplicates = Vec::new();
+    let mut first_conflict = None;
+    for path in objects {
+        if !required.reasons.contains_key(path) {
+            continue;
+        }
+        let syms = &required.object_syms[path];
+        let mut conflict = None;
+        for name in syms.strong_global_symbols() {
+            if let Some(first) = definers.get(name) {
+                conflict = Some((name, *first));
+                break;
+            }
+        }
+        let Some((name, first)) = conflict else {
+            for name in syms.strong_global_symbols() {
+                definers.insert(name, path);
+            }
+            continue;
+        };
+
+        let is_copy = required.object_syms[first].defines_all_of(syms);
+        if is_copy && drop_duplicate_objects {
+            info!(
+                "`{}` is a copy of `{}`, it will be skipped",
+                get_object_name_from_path(path),
+                get_object_name_from_path(first)
+            );
+            duplicates.push(path);
+            continue;
+        }
+        if first_conflict.is_some() {
+            continue;
+        }
+        let hint = if is_copy {
+            "all the global symbols of the second object are also defined by the first, \
+            if they are copies of the same object, pass --drop-duplicate-objects to only merge the first"
+        } else {
+            "only one definition can be merged, remove one of the objects from its library \
+            or rename the symbol in one of them"
+        };
+        first_conflict = Some(MergeError::DuplicateSymbol {
+            name: demangle::display_name(name),
+            first: get_object_name_from_path(first),
+            second: get_object_name_from_path(path),
+            hint: Some(hint.to_string()),
+        });
+    }
+    (duplicates, first_conflict)
+}
@@ -1,59 +1,130 @@
 use std::collections::{BTreeMap, HashSet};
 use std::path::PathBuf;
 
-use crate::{ArmergeKeepOrRemove, MergeError};
+use crate::MergeError;
 use rayon::prelude::*;
-use regex::Regex;
 use tracing::{event_enabled, info, Level};
 
 use crate::archives::get_object_name_from_path;
+use crate::objects::policy::SymbolPolicy;
 use crate::objects::syms::ObjectSyms;
+use crate::objects::ObjectTempDir;
+
+/// Why an object is merged in the output
+#[derive(Debug, Clone)]
+pub enum RequiredReason {
+    /// The object defines global symbols that are kept
+    ExportsKeptSymbols,
+    /// A required object uses a symbol defined by this object
+    RequiredBy { object: PathBuf, symbol: String },
+}
 
 fn add_deps_recursive(
-    objs_set: &mut HashSet<PathBuf>,
+    required: &mut BTreeMap<PathBuf, RequiredReason>,
     syms: &BTreeMap<PathBuf, ObjectSyms>,
+    excluded: &HashSet<&PathBuf>,
+    obj_path: &PathBuf,
     obj: &ObjectSyms,
 ) {
-    for dep in &obj.deps {
-        if objs_set.insert(dep.to_owned()) {
-            add_deps_recursive(objs_set, syms, syms.get(dep).unwrap());
+    let mut deps = obj.deps.iter().collect::<Vec<_>>();
+    deps.sort();
+    for dep in deps {
+        if required.contains_key(dep) || excluded.contains(dep) {
+            continue;
+        }
+        let dep_syms = syms.get(dep).unwrap();
+        let symbol = obj
+            .dependency_symbols(dep_syms)
+            .first()
+            .copied()
+            .unwrap_or_default();
+        required.insert(
+            dep.to_owned(),
+            RequiredReason::RequiredBy {
+                object: obj_path.to_owned(),
+                symbol: symbol.to_owned(),
+            },
+        );
+        add_deps_recursive(required, syms, excluded, dep, dep_syms);
+    }
+}
+
+/// The symbols and dependencies of all the objects, and the reason why each required object is merged
+pub struct RequiredObjects {
+    pub object_syms: BTreeMap<PathBuf, ObjectSyms>,
+    pub reasons: BTreeMap<PathBuf, RequiredReason>,
+}
+
+impl RequiredObjects {
+    /// Stops merging these objects, and the objects that were only merged because they used them
+    pub fn remove_objects(&mut self, removed: &[&PathBuf]) {
+        let excluded = removed.iter().copied().collect::<HashSet<_>>();
+        let reasons = required_reasons(&self.object_syms, &excluded);
+        if event_enabled!(Level::INFO) {
+            for obj in self.reasons.keys() {
+                if !reasons.contains_key(obj) && !excluded.contains(obj) {
+                    info!(
+                        "`{}` was only used by skipped objects, it will be skipped too",
+                        get_object_name_from_path(obj)
+                    )
+                }
+            }
         }
+        self.reasons = reasons;
     }
 }
 
+/// The objects that define kept symbols, and their dependencies, except for the excluded objects
+fn required_reasons(
+    object_syms: &BTreeMap<PathBuf, ObjectSyms>,
+    excluded: &HashSet<&PathBuf>,
+) -> BTreeMap<PathBuf, RequiredReason> {
+    let roots = object_syms
+        .iter()
+        .filter(|(obj_path, obj)| obj.has_exported_symbols && !excluded.contains(obj_path))
+        .collect::<Vec<_>>();
+    let mut required_objs = BTreeMap::new();
+    for (obj_path, _) in &roots {
+        required_objs.insert((*obj_path).clone(), RequiredReason::ExportsKeptSymbols);
+    }
+    for (obj_path, obj) in roots {
+        add_deps_recursive(&mut required_objs, object_syms, excluded, obj_path, obj);
+    }
+    required_objs
+}
+
 pub fn filter_required_objects(
-    objects: &[PathBuf],
-    keep_or_remove: ArmergeKeepOrRemove,
-    regexes: &[Regex],
-) -> Result<BTreeMap<PathBuf, ObjectSyms>, MergeError> {
+    objects: &ObjectTempDir,
+    policy: &SymbolPolicy,
+) -> Result<RequiredObjects, MergeError> {
     let mut object_syms = objects
-        .into_par_iter()
+        .objects
+        .par_iter()
         .map(|obj_path| {
+            let data = objects.read(obj_path)?;
             Ok::<_, MergeError>((
                 obj_path.to_owned(),
-                ObjectSyms::new(obj_path, keep_or_remove, regexes)?,
+                ObjectSyms::new(obj_path, &data, &policy.for_object(objects, obj_path).rules)?,
             ))
         })
         .collect::<Result<BTreeMap<PathBuf, ObjectSyms>, _>>()?;
     ObjectSyms::check_dependencies(&mut object_syms);
 
-    let mut required_objs = HashSet::new();
-    for (obj_path, obj) in object_syms.iter() {
-        if obj.has_exported_symbols {
-            if event_enabled!(Level::INFO) {
+    let required_objs = required_reasons(&object_syms, &HashSet::new());
+    if event_enabled!(Level::INFO) {
+        for (obj_path, reason) in &required_objs {
+            if let RequiredReason::ExportsKeptSymbols = reason {
                 info!(
                     "Will merge {:?} and its dependencies, as it contains global kept symbols",
                     get_object_name_from_path(obj_path),
                 );
             }
-            required_objs.insert(obj_path.clone());
-            add_deps_recursive(&mut required_objs, &object_syms, obj);
         }
     }
 
     if event_enabled!(Level::INFO) {
         for obj in object_syms.keys() {
-            if !required_objs.contains(obj) {
+            if !required_objs.contains_key(obj) {
                 info!(
                     "`{}` is not used by any kept objects, it will be skipped",
                     get_object_name_from_path(obj)
@@ -62,8 +133,8 @@ pub fn filter_required_objects(
         }
     }
 
-    Ok(object_syms
-        .into_iter()
-        .filter(|(obj_path, _)| required_objs.contains(obj_path))
-        .collect())
+    Ok(RequiredObjects {
+        object_syms,
+        reasons: required_objs,
+    })
 }
@@ -1,16 +1,45 @@
-use crate::MergeError;
+use crate::objects::builtin_link;
+use crate::objects::ObjectTempDir;
+use crate::{MergeError, MergeOptions};
+use rayon::prelude::*;
 use std::ffi::{OsStr, OsString};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
 use tracing::{debug, info};
 
+/// Links the objects into a single relocatable object.
+/// Objects that were extracted in memory are only written to disk when the system linker is needed.
 pub fn create_merged_object(
+    obj_dir: &ObjectTempDir,
     merged_path: &Path,
     extra_args: &[&OsStr],
     objects: impl IntoIterator<Item = impl AsRef<Path>>,
     silent: bool,
+    options: &MergeOptions,
 ) -> Result<(), MergeError> {
+    let objects = objects
+        .into_iter()
+        .map(|p| p.as_ref().to_owned())
+        .collect::<Vec<PathBuf>>();
+
+    // Extra arguments are linker flags, only the system linker knows what to do with them.
+    // Choosing a linker or linker flags in the environment also asks for the system linker.
+    let env_wants_system_linker =
+        std::env::var_os("LD").is_some() || std::env::var_os("ARMERGE_LDFLAGS").is_some();
+    if !options.use_system_linker && extra_args.is_empty() && !env_wants_system_linker {
+        let data = objects
+            .par_iter()
+            .map(|path| obj_dir.read(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        match builtin_link::parse_inputs(&objects, &data) {
+            Ok(inputs) => return builtin_link::link(merged_path, &inputs, silent),
+            Err(reason) => info!("Using the system linker: {}", reason),
+        }
+    }
+
+    obj_dir.write_to_disk(&objects)?;
+
     let ldflags = if let Ok(ldflags) = std::env::var("ARMERGE_LDFLAGS") {
         ldflags.split(' ').map(OsString::from).collect::<Vec<_>>()
     } else {
@@ -36,7 +65,7 @@ pub fn create_merged_object(
         objects
             .into_iter()
             .inspect(|_| count += 1)
-            .map(|p| p.as_ref().as_os_str().into()),
+            .map(|p| p.as_os_str().into()),
     );
 
     let trace_args = args
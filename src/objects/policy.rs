@@ -0,0 +1,108 @@
+use crate::objects::syms::ObjectSyms;
+use crate::objects::ObjectTempDir;
+use crate::symbol_rules::{exact_names_regex, SymbolAction, SymbolRules};
+use object::SymbolKind;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// The rules that decide which symbols are localized, and the patterns to show in the report
+pub struct SymbolPolicy {
+    pub rules: SymbolRules,
+    /// The pattern of each rule as the user wrote it, which with `--demangle` is the demangled pattern
+    /// that the regex of the rule was resolved from
+    shown_patterns: Vec<String>,
+    /// The policies of the input libraries that have their own rules, by library name
+    pub libraries: HashMap<String, SymbolPolicy>,
+}
+
+impl SymbolPolicy {
+    pub fn new(rules: SymbolRules) -> Self {
+        let shown_patterns = rules
+            .rules()
+            .iter()
+            .map(|(_, regex)| regex.as_str().to_owned())
+            .collect();
+        Self {
+            rules,
+            shown_patterns,
+            libraries: HashMap::new(),
+        }
+    }
+
+    /// The policy of the objects extracted from the input library of this object
+    pub fn for_object(&self, objects: &ObjectTempDir, path: &Path) -> &SymbolPolicy {
+        objects
+            .libraries
+            .get(path)
+            .and_then(|library| self.libraries.get(library))
+            .unwrap_or(self)
+    }
+
+    /// Adds a rule keeping the matching symbols to the policies that only keep a public API
+    pub fn keep_in_public_apis(&mut self, regex: &Regex) {
+        if self.rules.default_action() == SymbolAction::Localize {
+            self.push(SymbolAction::Keep, regex.clone(), regex.as_str().to_owned());
+        }
+        for policy in self.libraries.values_mut() {
+            policy.keep_in_public_apis(regex);
+        }
+    }
+
+    /// The rules of the symbols of the merged object. Without library policies, these are just the rules.
+    /// Otherwise each symbol is decided by the policy of the objects defining it, and stays global if any of them keeps it.
+    pub fn merged_rules(
+        &self,
+        objects: &ObjectTempDir,
+        object_syms: &BTreeMap<PathBuf, ObjectSyms>,
+        merged: &[PathBuf],
+    ) -> Cow<'_, SymbolRules> {
+        if self.libraries.is_empty() {
+            return Cow::Borrowed(&self.rules);
+        }
+
+        let mut kept = BTreeSet::new();
+        let mut localized = BTreeSet::new();
+        for path in merged {
+            let policy = self.for_object(objects, path);
+            for (name, kind) in &object_syms[path].defined_symbols {
+                if policy.decide(name, *kind).0 {
+                    kept.insert(name.as_str());
+                } else {
+                    localized.insert(name.as_str());
+                }
+            }
+        }
+
+        let mut rules = SymbolRules::new().with_default(SymbolAction::Keep);
+        if let Some(regex) = exact_names_regex(kept.iter().copied()) {
+            rules.push(SymbolAction::Keep, regex);
+        }
+        if let Some(regex) = exact_names_regex(localized.difference(&kept).copied()) {
+            rules.push(SymbolAction::Localize, regex);
+        }
+        Cow::Owned(rules)
+    }
+
+    /// Adds a rule whose pattern is shown differently from its regex
+    pub fn push(&mut self, action: SymbolAction, regex: Regex, shown_pattern: String) {
+        self.rules.push(action, regex);
+        self.shown_patterns.push(shown_pattern);
+    }
+
+    /// Whether the symbol stays global, and the pattern responsible for it
+    pub fn decide(&self, name: &str, kind: SymbolKind) -> (bool, Option<String>) {
+        if let Some(idx) = self.rules.matching_rule(name) {
+            let (action, _) = self.rules.rules()[idx];
+            return (
+                action == SymbolAction::Keep,
+                Some(self.shown_patterns[idx].clone()),
+            );
+        }
+        (
+            self.rules.filter_action(name, kind) == SymbolAction::Keep,
+            None,
+        )
+    }
+}
@@ -0,0 +1,95 @@
+use crate::objects::runtime_symbol_regexes;
+use crate::objects::system_filter::run_objcopy;
+use crate::{MergeError, MergeOptions};
+use object::{BinaryFormat, Object, ObjectSymbol, SymbolKind};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use tracing::info;
+
+/// Whether the options ask for any global symbol of the merged object to be renamed
+pub fn has_renames(options: &MergeOptions) -> bool {
+    options.symbol_prefix.is_some() || !options.symbol_renames.is_empty()
+}
+
+/// Renames the global symbols defined in the merged object, along with every reference to them.
+/// Symbols that are still undefined in the merged object belong to other libraries, and keep their name.
+/// The prefix isn't added to the unwind and personality symbols, which the runtime looks up by name.
+pub fn rename_symbols(
+    obj_dir: &Path,
+    merged_path: &Path,
+    options: &MergeOptions,
+) -> Result<(), MergeError> {
+    let data = std::fs::read(merged_path)?;
+    let file = object::File::parse(data.as_slice()).map_err(|e| MergeError::InvalidObject {
+        path: merged_path.to_owned(),
+        inner: e,
+    })?;
+    if !matches!(file.format(), BinaryFormat::Elf | BinaryFormat::MachO) {
+        return Err(MergeError::UnsupportedObject {
+            name: merged_path.display().to_string(),
+            reason: "symbols can only be renamed in ELF and Mach-O objects".to_string(),
+        });
+    }
+    let is_macho = file.format() == BinaryFormat::MachO;
+
+    let runtime_regexes = runtime_symbol_regexes();
+    let mut renames = BTreeMap::new();
+    for sym in file.symbols() {
+        if !sym.is_global()
+            || sym.is_undefined()
+            || sym.kind() == SymbolKind::Section
+            || sym.kind() == SymbolKind::File
+        {
+            continue;
+        }
+        let Ok(name) = sym.name() else {
+            continue;
+        };
+        let new_name = if let Some(new_name) = options.symbol_renames.get(name) {
+            new_name.to_owned()
+        } else if let Some(prefix) = &options.symbol_prefix {
+            if runtime_regexes.iter().any(|r| r.is_match(name)) {
+                continue;
+            }
+            prefixed_name(name, prefix, is_macho)
+        } else {
+            continue;
+        };
+        if new_name != name {
+            renames.insert(name.to_owned(), new_name);
+        }
+    }
+    drop(file);
+
+    info!("Renaming {} global symbols", renames.len());
+    if renames.is_empty() {
+        return Ok(());
+    }
+
+    let renames_path = obj_dir.join("rename.syms");
+    let mut renames_file = File::create(&renames_path)?;
+    for (name, new_name) in &renames {
+        writeln!(renames_file, "{name} {new_name}")?;
+    }
+    drop(renames_file);
+
+    run_objcopy(
+        vec![
+            OsString::from("--redefine-syms"),
+            renames_path.into_os_string(),
+            merged_path.as_os_str().to_owned(),
+        ],
+        "Failed to rename symbols",
+    )
+}
+
+/// C symbols on Mach-O start with an underscore, which must stay in front of the prefix
+fn prefixed_name(name: &str, prefix: &str, is_macho: bool) -> String {
+    match name.strip_prefix('_') {
+        Some(c_name) if is_macho => format!("_{prefix}{c_name}"),
+        _ => format!("{prefix}{name}"),
+    }
+}
@@ -0,0 +1,236 @@
+use crate::archives::get_object_name_from_path;
+use crate::demangle::demangle;
+use crate::objects::filter_deps::{RequiredObjects, RequiredReason};
+use crate::objects::policy::SymbolPolicy;
+use crate::objects::ObjectTempDir;
+use crate::symbol_rules::{SymbolAction, SymbolRules};
+use crate::MergeError;
+use object::SymbolKind;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// What merging would do to every object and global symbol of the inputs
+#[derive(Serialize)]
+struct Report {
+    mode: &'static str,
+    objects: Vec<ObjectReport>,
+    symbols: Vec<SymbolReport>,
+    /// The symbols that the merged objects use without defining them
+    undefined: Vec<UndefinedReport>,
+    libraries: Vec<LibraryReport>,
+}
+
+#[derive(Serialize)]
+struct ObjectReport {
+    name: String,
+    library: String,
+    decision: &'static str,
+    reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    required_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    required_symbol: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SymbolReport {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    demangled: Option<String>,
+    object: String,
+    decision: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pattern: Option<String>,
+}
+
+#[derive(Serialize)]
+struct UndefinedReport {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    demangled: Option<String>,
+    used_by: Vec<String>,
+}
+
+#[derive(Serialize, Default)]
+struct LibraryReport {
+    name: String,
+    objects: usize,
+    merged_objects: usize,
+    dropped_objects: usize,
+    kept_symbols: usize,
+    localized_symbols: usize,
+    hidden_symbols: usize,
+    dropped_symbols: usize,
+}
+
+/// Writes a JSON report of the objects that are merged or dropped, of the global symbols
+/// that are kept, localized or hidden, and of the external symbols used by the merged objects.
+/// Symbols are decided by the merged rules, like the symbol filters decide them.
+pub fn write_report(
+    report_path: &Path,
+    objects: &ObjectTempDir,
+    required: &RequiredObjects,
+    unresolved: &BTreeMap<String, Vec<PathBuf>>,
+    policy: &SymbolPolicy,
+    merged_rules: &SymbolRules,
+    hidden_visibility: bool,
+) -> Result<(), MergeError> {
+    let object_reports = objects
+        .objects
+        .iter()
+        .map(|path| {
+            let (decision, reason, required_by, required_symbol) = match required.reasons.get(path)
+            {
+                Some(RequiredReason::ExportsKeptSymbols) => (
+                    "merge",
+                    "defines kept global symbols".to_string(),
+                    None,
+                    None,
+                ),
+                Some(RequiredReason::RequiredBy { object, symbol }) => {
+                    let required_by = get_object_name_from_path(object);
+                    (
+                        "merge",
+                        format!("`{required_by}` uses `{symbol}`"),
+                        Some(required_by),
+                        Some(symbol.to_owned()),
+                    )
+                }
+                None => (
+                    "drop",
+                    "no kept global symbols, and not used by any merged object".to_string(),
+                    None,
+                    None,
+                ),
+            };
+            ObjectReport {
+                name: get_object_name_from_path(path),
+                library: objects.libraries.get(path).cloned().unwrap_or_default(),
+                decision,
+                reason,
+                required_by,
+                required_symbol,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let symbol_reports = objects
+        .objects
+        .par_iter()
+        .flat_map_iter(|path| {
+            object_symbol_reports(
+                objects,
+                path,
+                required,
+                policy,
+                merged_rules,
+                hidden_visibility,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut libraries = BTreeMap::<&str, LibraryReport>::new();
+    for (path, object) in objects.objects.iter().zip(&object_reports) {
+        let library = libraries.entry(object.library.as_str()).or_default();
+        library.objects += 1;
+        if required.reasons.contains_key(path) {
+            library.merged_objects += 1;
+        } else {
+            library.dropped_objects += 1;
+        }
+    }
+    let object_libraries = object_reports
+        .iter()
+        .map(|o| (o.name.as_str(), o.library.as_str()))
+        .collect::<BTreeMap<_, _>>();
+    for symbol in &symbol_reports {
+        let library = object_libraries[symbol.object.as_str()];
+        let library = libraries.entry(library).or_default();
+        match symbol.decision {
+            "keep" => library.kept_symbols += 1,
+            "localize" => library.localized_symbols += 1,
+            "hide" => library.hidden_symbols += 1,
+            _ => library.dropped_symbols += 1,
+        }
+    }
+    let library_reports = libraries
+        .into_iter()
+        .map(|(name, library)| LibraryReport {
+            name: name.to_owned(),
+            ..library
+        })
+        .collect();
+
+    let report = Report {
+        mode: match policy.rules.default_action() {
+            SymbolAction::Localize => "keep",
+            SymbolAction::Keep => "remove",
+        },
+        objects: object_reports,
+        symbols: symbol_reports,
+        undefined: unresolved
+            .iter()
+            .map(|(name, users)| UndefinedReport {
+                name: name.to_owned(),
+                demangled: demangle(name),
+                used_by: users.iter().map(|p| get_object_name_from_path(p)).collect(),
+            })
+            .collect(),
+        libraries: library_reports,
+    };
+    info!("Writing report to {}", report_path.display());
+    let file = File::create(report_path).map_err(MergeError::WritingReport)?;
+    let mut out = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut out, &report)
+        .map_err(|e| MergeError::WritingReport(e.into()))?;
+    out.write_all(b"\n")
+        .and_then(|_| out.flush())
+        .map_err(MergeError::WritingReport)
+}
+
+fn object_symbol_reports(
+    objects: &ObjectTempDir,
+    path: &Path,
+    required: &RequiredObjects,
+    policy: &SymbolPolicy,
+    merged_rules: &SymbolRules,
+    hidden_visibility: bool,
+) -> Vec<SymbolReport> {
+    let object_name = get_object_name_from_path(path);
+    let merged = required.reasons.contains_key(path);
+    let policy = policy.for_object(objects, path);
+    required.object_syms[path]
+        .defined_symbols
+        .iter()
+        .filter(|(_, kind)| *kind != SymbolKind::Section && *kind != SymbolKind::File)
+        .map(|(name, kind)| {
+            let (decision, pattern) = if merged {
+                // The decision is the one the symbol filters make, the pattern only explains it
+                // if the policy of this object is what made it
+                let action = merged_rules.filter_action(name, *kind);
+                let (keeps, pattern) = policy.decide(name, *kind);
+                let pattern = pattern.filter(|_| keeps == (action == SymbolAction::Keep));
+                let decision = match action {
+                    SymbolAction::Keep => "keep",
+                    SymbolAction::Localize if hidden_visibility => "hide",
+                    SymbolAction::Localize => "localize",
+                };
+                (decision, pattern)
+            } else {
+                ("drop", None)
+            };
+            SymbolReport {
+                name: name.to_owned(),
+                demangled: demangle(name),
+                object: object_name.clone(),
+                decision,
+                pattern,
+            }
+        })
+        .collect()
+}
@@ -1,33 +1,43 @@
-use crate::{ArmergeKeepOrRemove, MergeError};
-use object::{Object, ObjectSymbol, SymbolKind};
+use crate::symbol_rules::{SymbolAction, SymbolRules};
+use crate::MergeError;
+use object::{Object, ObjectComdat, ObjectSymbol, SymbolKind};
 use rayon::prelude::*;
-use regex::Regex;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 pub struct ObjectSyms {
     globals: HashSet<String>,
+    /// Global definitions that are not weak, common or in a COMDAT group, which the linker can't deduplicate
+    strong_globals: HashSet<String>,
     undefineds: HashSet<String>,
+    /// Every global symbol defined by this object, with its kind, to decide which ones to localize without parsing it again
+    pub defined_symbols: Vec<(String, SymbolKind)>,
     pub has_exported_symbols: bool,
     pub deps: HashSet<PathBuf>,
 }
 
 impl ObjectSyms {
-    pub fn new(
-        object_path: &Path,
-        keep_or_remove: ArmergeKeepOrRemove,
-        regexes: &[Regex],
-    ) -> Result<Self, MergeError> {
+    pub fn new(object_path: &Path, data: &[u8], rules: &SymbolRules) -> Result<Self, MergeError> {
         let mut globals = HashSet::new();
+        let mut strong_globals = HashSet::new();
         let mut undefineds = HashSet::new();
+        let mut defined_symbols = Vec::new();
         let mut has_exported_symbols = false;
 
-        let data = std::fs::read(object_path)?;
-        let file = object::File::parse(data.as_slice()).map_err(|e| MergeError::InvalidObject {
+        let file = object::File::parse(data).map_err(|e| MergeError::InvalidObject {
             path: object_path.to_owned(),
             inner: e,
         })?;
+        let comdat_sections = file
+            .comdats()
+            .flat_map(|comdat| comdat.sections())
+            .collect::<HashSet<_>>();
         for sym in file.symbols() {
+            if sym.is_global() && !sym.is_undefined() {
+                if let Ok(name) = sym.name() {
+                    defined_symbols.push((name.to_owned(), sym.kind()));
+                }
+            }
             if sym.kind() != SymbolKind::Text
                 && sym.kind() != SymbolKind::Data
                 && sym.kind() != SymbolKind::Unknown
@@ -40,6 +50,12 @@ impl ObjectSyms {
                     undefineds.insert(name.to_owned());
                 } else if sym.is_global() || sym.is_weak() {
                     globals.insert(name.to_owned());
+                    let in_comdat = sym
+                        .section_index()
+                        .is_some_and(|section| comdat_sections.contains(&section));
+                    if !sym.is_weak() && !sym.is_common() && !in_comdat {
+                        strong_globals.insert(name.to_owned());
+                    }
                 }
             }
 
@@ -48,54 +64,91 @@ impl ObjectSyms {
             }
 
             if let Ok(name) = sym.name() {
-                for regex in regexes {
-                    let keep_sym_condition = if keep_or_remove == ArmergeKeepOrRemove::KeepSymbols {
-                        regex.is_match(name)
-                    } else {
-                        !regex.is_match(name)
-                    };
-                    if keep_sym_condition {
-                        has_exported_symbols = true;
-                        break;
-                    }
+                if rules.action(name) == SymbolAction::Keep {
+                    has_exported_symbols = true;
                 }
             }
         }
 
         Ok(Self {
             globals,
+            strong_globals,
             undefineds,
+            defined_symbols,
             has_exported_symbols,
             deps: Default::default(),
         })
     }
 
-    pub fn has_dependency(&self, obj_syms: &ObjectSyms) -> bool {
-        for undef in &self.undefineds {
-            if obj_syms.globals.contains(undef) {
-                return true;
-            }
-        }
-        false
+    /// The global symbols defined by this object, sorted by name
+    pub fn global_symbols(&self) -> Vec<&str> {
+        let mut symbols = self.globals.iter().map(String::as_str).collect::<Vec<_>>();
+        symbols.sort_unstable();
+        symbols
+    }
+
+    /// The symbols this object uses without defining them, sorted by name
+    pub fn undefined_symbols(&self) -> Vec<&str> {
+        let mut symbols = self
+            .undefineds
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        symbols.sort_unstable();
+        symbols
+    }
+
+    /// The global symbols defined by this object that would conflict with another definition, sorted by name
+    pub fn strong_global_symbols(&self) -> Vec<&str> {
+        let mut symbols = self
+            .strong_globals
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        symbols.sort_unstable();
+        symbols
     }
 
+    /// Whether this object defines all the global symbols of the other object, e.g. because both are copies of the same object
+    pub fn defines_all_of(&self, obj_syms: &ObjectSyms) -> bool {
+        obj_syms.globals.is_subset(&self.globals)
+    }
+
+    /// The symbols this object uses from the other object, sorted by name
+    pub fn dependency_symbols(&self, obj_syms: &ObjectSyms) -> Vec<&str> {
+        let mut symbols = self
+            .undefineds
+            .iter()
+            .filter(|undef| obj_syms.globals.contains(*undef))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        symbols.sort_unstable();
+        symbols
+    }
+
+    /// Finds the objects defining the undefined symbols of each object, using an index of the definers of each symbol
     pub fn check_dependencies(object_syms: &mut BTreeMap<PathBuf, Self>) {
+        let mut definers = HashMap::<&str, Vec<&PathBuf>>::new();
+        for (path, syms) in object_syms.iter() {
+            for name in &syms.globals {
+                definers.entry(name).or_default().push(path);
+            }
+        }
         let deps_map = object_syms
             .par_iter()
-            .map(|(left_path, left_syms)| {
-                let mut deps = HashSet::new();
-                for (right_path, right_syms) in object_syms.iter() {
-                    if std::ptr::eq(left_path, right_path) {
-                        continue;
-                    }
-
-                    if left_syms.has_dependency(right_syms) {
-                        deps.insert(right_path.to_owned());
-                    }
-                }
-                (left_path.to_owned(), deps)
+            .map(|(path, syms)| {
+                let deps = syms
+                    .undefineds
+                    .iter()
+                    .filter_map(|undef| definers.get(undef.as_str()))
+                    .flatten()
+                    .filter(|dep| **dep != path)
+                    .map(|dep| (*dep).to_owned())
+                    .collect::<HashSet<_>>();
+                (path.to_owned(), deps)
             })
             .collect::<BTreeMap<_, _>>();
+        drop(definers);
         for (path, deps) in deps_map {
             object_syms.get_mut(&path).unwrap().deps = deps;
         }
@@ -1,89 +1,79 @@
 use goblin::{peek_bytes, Hint};
-use std::collections::HashSet;
-use std::ffi::OsString;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
 
 use crate::objects::merge::create_merged_object;
-use crate::{ArmergeKeepOrRemove, MergeError};
-use object::{Object, ObjectSymbol, SymbolKind};
-use regex::Regex;
+use crate::symbol_rules::{SymbolAction, SymbolRules};
+use crate::{MergeError, MergeOptions};
+use crate::objects::syms::ObjectSyms;
+use crate::objects::visibility;
+use crate::objects::ObjectTempDir;
+use object::{Object, ObjectSymbol};
 use std::fs::File;
 use tracing::info;
 
 pub fn create_filtered_merged_object(
+    obj_dir: &ObjectTempDir,
     merged_path: &Path,
+    extra_args: &[&OsStr],
     objects: impl IntoIterator<Item = impl AsRef<Path>>,
     filter_list: &Path,
+    options: &MergeOptions,
 ) -> Result<(), MergeError> {
-    create_merged_object(merged_path, &[], objects, false)?;
+    create_merged_object(obj_dir, merged_path, extra_args, objects, false, options)?;
     filter_symbols(merged_path, filter_list)?;
 
     Ok(())
 }
 
 fn create_filtered_merged_macho_object(
+    obj_dir: &ObjectTempDir,
     merged_path: &Path,
     objects: impl IntoIterator<Item = impl AsRef<Path>>,
     filter_list: &Path,
+    options: &MergeOptions,
 ) -> Result<(), MergeError> {
     let extra_args = &["-unexported_symbols_list".as_ref(), filter_list.as_os_str()];
     let merged_firstpass_path = merged_path.parent().unwrap().join("merged_firstpass.o");
-    create_merged_object(&merged_firstpass_path, extra_args, objects, false)?;
-    create_merged_object(merged_path, &[], [&merged_firstpass_path], true)?;
+    create_merged_object(
+        obj_dir,
+        &merged_firstpass_path,
+        extra_args,
+        objects,
+        false,
+        options,
+    )?;
+    create_merged_object(
+        obj_dir,
+        merged_path,
+        &[],
+        [&merged_firstpass_path],
+        true,
+        options,
+    )?;
 
     Ok(())
 }
 
-pub fn create_symbol_filter_list(
+pub fn create_symbol_filter_list<'a>(
     object_dir: &Path,
-    objects: impl IntoIterator<Item = impl AsRef<Path>>,
-    keep_or_remove: ArmergeKeepOrRemove,
-    regexes: &[Regex],
+    objects: impl IntoIterator<Item = &'a ObjectSyms>,
+    rules: &SymbolRules,
 ) -> Result<PathBuf, MergeError> {
-    let filter_path = object_dir.join("localize.syms");
-    let mut filter_syms = HashSet::new();
-    let mut kept_count = 0;
-
-    for object_path in objects.into_iter() {
-        let object_path = object_path.as_ref();
-        let data = std::fs::read(object_path)?;
-        let file = object::File::parse(data.as_slice()).map_err(|e| MergeError::InvalidObject {
-            path: object_path.to_owned(),
-            inner: e,
-        })?;
-        'next_symbol: for sym in file.symbols() {
-            if keep_or_remove == ArmergeKeepOrRemove::KeepSymbols
-                && (!sym.is_global()
-                    || sym.is_undefined()
-                    || (sym.kind() != SymbolKind::Text
-                        && sym.kind() != SymbolKind::Data
-                        && sym.kind() != SymbolKind::Unknown/* ASM functions often end up unknown */))
-            {
-                continue;
-            }
-            if let Ok(name) = sym.name() {
-                for regex in regexes {
-                    if regex.is_match(name) {
-                        if keep_or_remove == ArmergeKeepOrRemove::KeepSymbols {
-                            kept_count += 1;
-                        } else {
-                            filter_syms.insert(name.to_owned());
-                        }
-                        continue 'next_symbol;
-                    }
-                }
+    let (filter_syms, kept_count) = symbol_filter_list(objects, rules);
+    write_symbol_filter_list(object_dir, filter_syms, kept_count)
+}
 
-                if keep_or_remove == ArmergeKeepOrRemove::KeepSymbols {
-                    filter_syms.insert(name.to_owned());
-                } else {
-                    kept_count += 1;
-                }
-            }
-        }
-    }
+fn write_symbol_filter_list(
+    object_dir: &Path,
+    filter_syms: BTreeSet<String>,
+    kept_count: usize,
+) -> Result<PathBuf, MergeError> {
+    let filter_path = object_dir.join("localize.syms");
     info!(
         "Localizing {} symbols, keeping {} globals",
         filter_syms.len(),
@@ -99,18 +89,103 @@ pub fn create_symbol_filter_list(
     Ok(filter_path)
 }
 
+/// The global symbols that the rules don't keep, and the number of symbols they keep
+fn symbol_filter_list<'a>(
+    objects: impl IntoIterator<Item = &'a ObjectSyms>,
+    rules: &SymbolRules,
+) -> (BTreeSet<String>, usize) {
+    let mut filter_syms = BTreeSet::new();
+    let mut kept_count = 0;
+
+    for object_syms in objects.into_iter() {
+        for (name, kind) in &object_syms.defined_symbols {
+            let action = rules.filter_action(name, *kind);
+            match action {
+                SymbolAction::Keep => kept_count += 1,
+                SymbolAction::Localize => {
+                    filter_syms.insert(name.to_owned());
+                }
+            }
+        }
+    }
+    (filter_syms, kept_count)
+}
+
+/// Linker arguments that drop the sections unreachable from the global symbols that are not filtered.
+/// The roots are passed in a response file, since there can be many of them.
+fn gc_sections_args(
+    object_dir: &Path,
+    objects: &[PathBuf],
+    object_syms: &BTreeMap<PathBuf, ObjectSyms>,
+    filter_syms: &BTreeSet<String>,
+) -> Result<Vec<OsString>, MergeError> {
+    let roots = objects
+        .iter()
+        .flat_map(|path| &object_syms[path].defined_symbols)
+        .map(|(name, _)| name)
+        .filter(|name| !filter_syms.contains(*name))
+        .collect::<BTreeSet<_>>();
+    info!(
+        "Collecting the sections unreachable from {} global symbols",
+        roots.len()
+    );
+
+    let roots_path = object_dir.join("gc_roots.args");
+    let mut roots_file = File::create(&roots_path)?;
+    for name in roots {
+        writeln!(roots_file, "--undefined={name}")?;
+    }
+
+    let mut response_file = OsString::from("@");
+    response_file.push(roots_path.as_os_str());
+    Ok(vec![OsString::from("--gc-sections"), response_file])
+}
+
+/// Removes the undefined symbols that only the collected sections used, so that the final link doesn't look for them
+fn strip_unreferenced_undefined_symbols(
+    object_dir: &Path,
+    object_path: &Path,
+) -> Result<(), MergeError> {
+    let data = std::fs::read(object_path)?;
+    let file = object::File::parse(data.as_slice()).map_err(|e| MergeError::InvalidObject {
+        path: object_path.to_owned(),
+        inner: e,
+    })?;
+    let undefined_path = object_dir.join("undefined.syms");
+    let mut undefined_file = File::create(&undefined_path)?;
+    for sym in file.symbols().filter(|sym| sym.is_undefined()) {
+        if let Ok(name) = sym.name() {
+            writeln!(undefined_file, "{name}")?;
+        }
+    }
+    drop(undefined_file);
+
+    // Symbols that relocations still use are never stripped
+    let args = vec![
+        OsString::from("--strip-unneeded-symbols"),
+        undefined_path.into_os_string(),
+        object_path.as_os_str().to_owned(),
+    ];
+    run_objcopy(args, "Failed to strip unreferenced undefined symbols")
+}
+
 fn filter_symbols(object_path: &Path, filter_list_path: &Path) -> Result<(), MergeError> {
+    let args = vec![
+        OsString::from("--localize-symbols"),
+        filter_list_path.as_os_str().to_owned(),
+        object_path.as_os_str().to_owned(),
+    ];
+    run_objcopy(args, "Failed to filter symbols")
+}
+
+/// Runs `$OBJCOPY` (or `llvm-objcopy`) to modify an object in place
+pub fn run_objcopy(args: Vec<OsString>, reason: &str) -> Result<(), MergeError> {
     let objcopy_path = if let Some(var) = std::env::var_os("OBJCOPY") {
         var
     } else {
         OsString::from_str("llvm-objcopy").unwrap()
     };
 
-    let args = vec![
-        OsString::from("--localize-symbols"),
-        filter_list_path.as_os_str().to_owned(),
-        object_path.as_os_str().to_owned(),
-    ];
     info!(
         "{} {}",
         objcopy_path.to_string_lossy(),
@@ -131,7 +206,7 @@ fn filter_symbols(object_path: &Path, filter_list_path: &Path) -> Result<(), Mer
         Ok(())
     } else {
         Err(MergeError::ExternalToolError {
-            reason: "Failed to filter symbols".to_string(),
+            reason: reason.to_string(),
             tool: objcopy_path.to_string_lossy().to_string(),
             args,
             stdout: String::from_utf8_lossy(&output.stdout).to_string(),
@@ -141,33 +216,88 @@ fn filter_symbols(object_path: &Path, filter_list_path: &Path) -> Result<(), Mer
 }
 
 pub fn merge_required_macho_objects(
-    obj_dir: &Path,
+    obj_dir: &ObjectTempDir,
     merged_path: &Path,
     objects: &[PathBuf],
-    keep_or_remove: ArmergeKeepOrRemove,
-    regexes: &[Regex],
+    object_syms: &BTreeMap<PathBuf, ObjectSyms>,
+    rules: &SymbolRules,
+    options: &MergeOptions,
 ) -> Result<(), MergeError> {
-    let filter_path = create_symbol_filter_list(obj_dir, objects, keep_or_remove, regexes)?;
-    create_filtered_merged_macho_object(merged_path, objects, &filter_path)
+    let filter_path = create_symbol_filter_list(
+        obj_dir.dir.path(),
+        objects.iter().map(|path| &object_syms[path]),
+        rules,
+    )?;
+    if options.hidden_visibility {
+        // The unexported symbols become private externs, which stay global to the final link
+        let extra_args = &[
+            "-unexported_symbols_list".as_ref(),
+            filter_path.as_os_str(),
+            "-keep_private_externs".as_ref(),
+        ];
+        return create_merged_object(obj_dir, merged_path, extra_args, objects, false, options);
+    }
+    create_filtered_merged_macho_object(obj_dir, merged_path, objects, &filter_path, options)
 }
 
 pub fn merge_required_objects(
-    obj_dir: &Path,
+    obj_dir: &ObjectTempDir,
     merged_path: &Path,
     objects: &[PathBuf],
-    keep_or_remove: ArmergeKeepOrRemove,
-    regexes: &[Regex],
+    object_syms: &BTreeMap<PathBuf, ObjectSyms>,
+    rules: &SymbolRules,
+    options: &MergeOptions,
 ) -> Result<(), MergeError> {
-    let filter_path = create_symbol_filter_list(obj_dir, objects, keep_or_remove, regexes)?;
-    create_filtered_merged_object(merged_path, objects, &filter_path)?;
+    let (filter_syms, kept_count) =
+        symbol_filter_list(objects.iter().map(|path| &object_syms[path]), rules);
+    let gc_args = if options.gc_sections {
+        // Hidden symbols stay global, so other objects of the final link can still reference their sections
+        let no_filter_syms = BTreeSet::new();
+        let gc_filter_syms = if options.hidden_visibility {
+            &no_filter_syms
+        } else {
+            &filter_syms
+        };
+        gc_sections_args(obj_dir.dir.path(), objects, object_syms, gc_filter_syms)?
+    } else {
+        Vec::new()
+    };
+    let gc_args = gc_args.iter().map(OsString::as_os_str).collect::<Vec<_>>();
+
+    if options.hidden_visibility {
+        info!(
+            "Hiding {} symbols, keeping {} globals",
+            filter_syms.len(),
+            kept_count
+        );
+        create_merged_object(obj_dir, merged_path, &gc_args, objects, false, options)?;
+        if options.gc_sections {
+            strip_unreferenced_undefined_symbols(obj_dir.dir.path(), merged_path)?;
+        }
+        // Hidden symbols stay global, so their COMDAT groups can be folded like any other
+        return visibility::hide_elf_symbols(merged_path, &filter_syms);
+    }
+
+    let filter_path = write_symbol_filter_list(obj_dir.dir.path(), filter_syms, kept_count)?;
+    create_filtered_merged_object(
+        obj_dir,
+        merged_path,
+        &gc_args,
+        objects,
+        &filter_path,
+        options,
+    )?;
+    if options.gc_sections {
+        strip_unreferenced_undefined_symbols(obj_dir.dir.path(), merged_path)?;
+    }
 
     // If a symbol we localize is in a COMDAT section group, we also want to turn it into a regular
     // section group. Otherwise the local symbol is not really local, because the containing section
     // could later get COMDAT-folded with other (potentially incompatible) object files.
-    demote_elf_comdats(merged_path, regexes)
+    demote_elf_comdats(merged_path, rules)
 }
 
-fn demote_elf_comdats(merged_path: &Path, keep_regexes: &[Regex]) -> Result<(), MergeError> {
+fn demote_elf_comdats(merged_path: &Path, rules: &SymbolRules) -> Result<(), MergeError> {
     let mut file = File::open(merged_path)?;
     let hint_bytes = &mut [0u8; 16];
     file.read_exact(hint_bytes)?;
@@ -183,7 +313,16 @@ fn demote_elf_comdats(merged_path: &Path, keep_regexes: &[Regex]) -> Result<(),
 
                 let mut data = Vec::new();
                 file.read_to_end(&mut data)?;
-                objpoke::elf::demote_comdat_groups(data, keep_regexes)
+                let keep_regexes = {
+                    let merged = object::File::parse(data.as_slice()).map_err(|e| {
+                        MergeError::InvalidObject {
+                            path: merged_path.to_owned(),
+                            inner: e,
+                        }
+                    })?;
+                    rules.keep_regexes(merged.symbols().filter_map(|sym| sym.name().ok()))
+                };
+                objpoke::elf::demote_comdat_groups(data, &keep_regexes)
                     .map_err(|e| MergeError::InternalError(e.into()))?
             }
             // We don't know about needing to demote any COMDATs in PE/Mach-O files
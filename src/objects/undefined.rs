@@ -0,0 +1,68 @@
+use crate::archives::get_object_name_from_path;
+use crate::demangle::{demangle, display_name};
+use crate::objects::filter_deps::RequiredObjects;
+use crate::MergeError;
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use tracing::{event_enabled, info, Level};
+
+/// The symbols that the merged objects use but don't define, with the merged objects using them.
+/// The merged library expects them from other libraries (libc, libstdc++, SDKs...).
+pub fn unresolved_symbols(required: &RequiredObjects) -> BTreeMap<String, Vec<PathBuf>> {
+    let defined = required
+        .reasons
+        .keys()
+        .flat_map(|path| required.object_syms[path].global_symbols())
+        .collect::<HashSet<_>>();
+    let mut unresolved = BTreeMap::<String, Vec<PathBuf>>::new();
+    for path in required.reasons.keys() {
+        for name in required.object_syms[path].undefined_symbols() {
+            if !defined.contains(name) {
+                unresolved
+                    .entry(name.to_owned())
+                    .or_default()
+                    .push(path.to_owned());
+            }
+        }
+    }
+
+    if event_enabled!(Level::INFO) {
+        info!(
+            "The merged objects use {} external symbols",
+            unresolved.len()
+        );
+        for name in unresolved.keys() {
+            info!("External symbol `{}`", display_name(name));
+        }
+    }
+    unresolved
+}
+
+/// Fails if the merged objects use external symbols whose mangled or demangled name matches none of the allowed patterns
+pub fn check_allowed(
+    unresolved: &BTreeMap<String, Vec<PathBuf>>,
+    allowed: &[Regex],
+) -> Result<(), MergeError> {
+    let symbols = unresolved
+        .iter()
+        .filter(|(name, _)| {
+            let demangled = demangle(name);
+            !allowed
+                .iter()
+                .any(|r| r.is_match(name) || demangled.as_deref().is_some_and(|d| r.is_match(d)))
+        })
+        .map(|(name, users)| {
+            format!(
+                "`{}` (used by `{}`)",
+                display_name(name),
+                get_object_name_from_path(&users[0])
+            )
+        })
+        .collect::<Vec<_>>();
+    if symbols.is_empty() {
+        Ok(())
+    } else {
+        Err(MergeError::DisallowedUndefinedSymbols { symbols })
+    }
+}
@@ -0,0 +1,73 @@
+use crate::MergeError;
+use object::elf;
+use object::read::elf::{FileHeader, SectionHeader, Sym};
+use object::FileKind;
+use std::collections::BTreeSet;
+use std::path::Path;
+use tracing::info;
+
+/// Sets the visibility of these global symbols of an ELF object to hidden, in place.
+/// Unlike localized symbols, hidden symbols stay global, so other objects of the final link can still use them.
+pub fn hide_elf_symbols(object_path: &Path, names: &BTreeSet<String>) -> Result<(), MergeError> {
+    let mut data = std::fs::read(object_path)?;
+    let invalid_object = |e| MergeError::InvalidObject {
+        path: object_path.to_owned(),
+        inner: e,
+    };
+    let offsets = match FileKind::parse(data.as_slice()).map_err(invalid_object)? {
+        FileKind::Elf32 => st_other_offsets::<elf::FileHeader32<object::Endianness>>(&data, names),
+        FileKind::Elf64 => st_other_offsets::<elf::FileHeader64<object::Endianness>>(&data, names),
+        _ => {
+            return Err(MergeError::UnsupportedObject {
+                name: object_path.display().to_string(),
+                reason: "symbols can only be hidden in ELF and Mach-O objects".to_string(),
+            })
+        }
+    }
+    .map_err(invalid_object)?;
+
+    info!(
+        "Hiding {} global symbols in {}",
+        offsets.len(),
+        object_path.display()
+    );
+    for offset in offsets {
+        data[offset] = (data[offset] & !0x3) | elf::STV_HIDDEN;
+    }
+    std::fs::write(object_path, data)?;
+    Ok(())
+}
+
+/// The offsets in the file of the `st_other` field of the defined global symbols with these names
+fn st_other_offsets<Elf: FileHeader<Endian = object::Endianness>>(
+    data: &[u8],
+    names: &BTreeSet<String>,
+) -> object::Result<Vec<usize>> {
+    let header = Elf::parse(data)?;
+    let endian = header.endian()?;
+    let sections = header.sections(endian, data)?;
+    let symbols = sections.symbols(endian, data, elf::SHT_SYMTAB)?;
+    let symtab_offset: u64 = sections
+        .section(symbols.section())?
+        .sh_offset(endian)
+        .into();
+    // `st_other` follows `st_name`, `st_value` and `st_size` in ELF32 symbols, but directly follows `st_info` in ELF64
+    let st_other_offset = if header.is_type_64() { 5 } else { 13 };
+
+    let mut offsets = Vec::new();
+    for (index, sym) in symbols.enumerate() {
+        let bind = sym.st_bind();
+        if (bind != elf::STB_GLOBAL && bind != elf::STB_WEAK) || sym.is_undefined(endian) {
+            continue;
+        }
+        let name = std::str::from_utf8(sym.name(endian, symbols.strings())?).unwrap_or_default();
+        if names.contains(name) {
+            offsets.push(
+                symtab_offset as usize
+                    + index.0 * std::mem::size_of::<Elf::Sym>()
+                    + st_other_offset,
+            );
+        }
+    }
+    Ok(offsets)
+}
@@ -0,0 +1,240 @@
+use crate::archives::get_object_name_from_path;
+use crate::demangle::{demangle, display_name};
+use crate::objects::filter_deps::{RequiredObjects, RequiredReason};
+use crate::objects::policy::SymbolPolicy;
+use crate::objects::syms::ObjectSyms;
+use crate::objects::ObjectTempDir;
+use crate::symbol_rules::{SymbolAction, SymbolRules};
+use object::SymbolKind;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// Explains why an object (by member name) or a global symbol is merged, dropped, kept or localized.
+/// Merged objects are explained by the shortest chain of dependencies from an object defining kept symbols.
+/// Symbols are decided by `merged_rules`, the rules the symbol filters apply to the merged object.
+pub fn explain(
+    objects: &ObjectTempDir,
+    required: &RequiredObjects,
+    policy: &SymbolPolicy,
+    merged_rules: &SymbolRules,
+    hidden_visibility: bool,
+    query: &str,
+) -> String {
+    let members = matching_objects(objects, query);
+    if !members.is_empty() {
+        let mut answer = String::new();
+        for path in members {
+            answer += &explain_object(objects, required, policy, merged_rules, path);
+        }
+        return answer;
+    }
+
+    let definers = objects
+        .objects
+        .iter()
+        .filter_map(|path| {
+            let syms = &required.object_syms[path];
+            let name = syms.global_symbols().into_iter().find(|name| {
+                *name == query
+                    || name.strip_prefix('_') == Some(query)
+                    || demangle(name).as_deref() == Some(query)
+            })?;
+            Some((path, name))
+        })
+        .collect::<Vec<_>>();
+    if definers.is_empty() {
+        return format!(
+            "`{query}` is neither an input object nor a global symbol defined by one\n"
+        );
+    }
+    let mut answer = String::new();
+    for (path, name) in definers {
+        answer += &explain_symbol(
+            objects,
+            required,
+            policy,
+            merged_rules,
+            hidden_visibility,
+            path,
+            name,
+        );
+    }
+    answer
+}
+
+/// Objects are matched by their full `library@member` name, or by their member name
+fn matching_objects<'a>(objects: &'a ObjectTempDir, query: &str) -> Vec<&'a PathBuf> {
+    objects
+        .objects
+        .iter()
+        .filter(|path| {
+            let name = get_object_name_from_path(path);
+            name == query
+                || name.ends_with(&format!("@{query}"))
+                || objects.names.get(*path).is_some_and(|n| n == query)
+        })
+        .collect()
+}
+
+fn explain_object(
+    objects: &ObjectTempDir,
+    required: &RequiredObjects,
+    policy: &SymbolPolicy,
+    merged_rules: &SymbolRules,
+    path: &PathBuf,
+) -> String {
+    let name = get_object_name_from_path(path);
+    let Some(chain) = shortest_chain(required, path) else {
+        return format!(
+            "`{name}` is dropped: it defines no kept global symbol, and no merged object uses its symbols\n"
+        );
+    };
+    let mut answer = format!("`{name}` is merged:\n");
+    write_chain(&mut answer, objects, required, policy, merged_rules, &chain);
+    answer
+}
+
+fn explain_symbol(
+    objects: &ObjectTempDir,
+    required: &RequiredObjects,
+    policy: &SymbolPolicy,
+    merged_rules: &SymbolRules,
+    hidden_visibility: bool,
+    path: &PathBuf,
+    symbol: &str,
+) -> String {
+    let object_name = get_object_name_from_path(path);
+    let symbol_name = display_name(symbol);
+    let Some(chain) = shortest_chain(required, path) else {
+        return format!(
+            "`{symbol_name}` is dropped: it is defined by `{object_name}`, which defines no kept global symbol, \
+            and no merged object uses its symbols\n"
+        );
+    };
+    // The decision is the one the symbol filters make, the policy of this object only explains it
+    // when it agrees, since another merged object defining the symbol can keep it global
+    let kind = symbol_kind(&required.object_syms[path], symbol);
+    let policy = policy.for_object(objects, path);
+    let (keeps, pattern) = policy.decide(symbol, kind);
+    let localized = if hidden_visibility {
+        "hidden"
+    } else {
+        "localized"
+    };
+    let decision = match (merged_rules.filter_action(symbol, kind), pattern) {
+        (SymbolAction::Keep, _) if !keeps => {
+            "stays global, another merged object that defines it keeps it".to_string()
+        }
+        (SymbolAction::Keep, Some(pattern)) => {
+            format!("stays global, it matches the kept pattern `{pattern}`")
+        }
+        (SymbolAction::Keep, None) if policy.rules.default_action() == SymbolAction::Keep => {
+            "stays global, it matches no pattern and unmatched symbols are kept".to_string()
+        }
+        (SymbolAction::Keep, None) => format!(
+            "stays global, it matches no pattern and only functions and variables are {localized}"
+        ),
+        (SymbolAction::Localize, Some(pattern)) => {
+            format!("is {localized}, it matches the removed pattern `{pattern}`")
+        }
+        (SymbolAction::Localize, None) => {
+            format!("is {localized}, it matches no pattern and unmatched symbols are {localized}")
+        }
+    };
+    let mut answer = format!("`{symbol_name}` {decision}, and is merged with `{object_name}`:\n");
+    write_chain(&mut answer, objects, required, policy, merged_rules, &chain);
+    answer
+}
+
+/// The shortest chain of required objects from an object defining kept symbols to the target,
+/// following the same undefined to defined symbol edges as the object filter
+fn shortest_chain<'a>(
+    required: &'a RequiredObjects,
+    target: &'a PathBuf,
+) -> Option<Vec<&'a PathBuf>> {
+    if !required.reasons.contains_key(target) {
+        return None;
+    }
+    let mut parents = HashMap::<&PathBuf, Option<&PathBuf>>::new();
+    let mut queue = VecDeque::new();
+    for (path, reason) in &required.reasons {
+        if let RequiredReason::ExportsKeptSymbols = reason {
+            parents.insert(path, None);
+            queue.push_back(path);
+        }
+    }
+    while let Some(path) = queue.pop_front() {
+        if path == target {
+            let mut chain = vec![path];
+            while let Some(&Some(parent)) = parents.get(chain.last().unwrap()) {
+                chain.push(parent);
+            }
+            chain.reverse();
+            return Some(chain);
+        }
+        let mut deps = required.object_syms[path].deps.iter().collect::<Vec<_>>();
+        deps.sort();
+        for dep in deps {
+            if !parents.contains_key(dep) {
+                parents.insert(dep, Some(path));
+                queue.push_back(dep);
+            }
+        }
+    }
+    None
+}
+
+fn write_chain(
+    answer: &mut String,
+    objects: &ObjectTempDir,
+    required: &RequiredObjects,
+    policy: &SymbolPolicy,
+    merged_rules: &SymbolRules,
+    chain: &[&PathBuf],
+) {
+    let root = chain[0];
+    let root_name = get_object_name_from_path(root);
+    let policy = policy.for_object(objects, root);
+    let root_syms = &required.object_syms[root];
+    let kept = root_syms.global_symbols().into_iter().find_map(|name| {
+        let kind = symbol_kind(root_syms, name);
+        if merged_rules.filter_action(name, kind) != SymbolAction::Keep {
+            return None;
+        }
+        let (keeps, pattern) = policy.decide(name, kind);
+        Some((name, pattern.filter(|_| keeps)))
+    });
+    let _ = match kept {
+        Some((name, Some(pattern))) => writeln!(
+            answer,
+            "  `{root_name}` defines the kept symbol `{}`, which matches `{pattern}`",
+            display_name(name)
+        ),
+        Some((name, None)) => writeln!(
+            answer,
+            "  `{root_name}` defines the kept symbol `{}`",
+            display_name(name)
+        ),
+        None => writeln!(answer, "  `{root_name}` defines kept global symbols"),
+    };
+    for pair in chain.windows(2) {
+        let (user, dep) = (pair[0], pair[1]);
+        let symbols = required.object_syms[user].dependency_symbols(&required.object_syms[dep]);
+        let _ = writeln!(
+            answer,
+            "  `{}` uses `{}` from `{}`",
+            get_object_name_from_path(user),
+            symbols.first().map(|s| display_name(s)).unwrap_or_default(),
+            get_object_name_from_path(dep)
+        );
+    }
+}
+
+/// The kind of a global symbol defined by the object
+fn symbol_kind(syms: &ObjectSyms, name: &str) -> SymbolKind {
+    syms.defined_symbols
+        .iter()
+        .find(|(defined, _)| defined == name)
+        .map_or(SymbolKind::Unknown, |(_, kind)| *kind)
+}
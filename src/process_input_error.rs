@@ -13,6 +13,10 @@ pub enum ProcessInputError {
     ReadingArchive { name: String, inner: io::Error },
     #[error("input archives don't seem to contain any objects")]
     Empty,
+    #[error("thin archive {name} has no base dir to find its members in, see `InputLibrary::with_base_dir`")]
+    ThinArchiveWithoutBaseDir { name: String },
+    #[error("thin archives can only be written for ELF objects")]
+    ThinArchiveUnsupported,
     #[error("error writing extracted object file {path}: {inner})")]
     ExtractingObject { path: PathBuf, inner: io::Error },
 }
@@ -0,0 +1,130 @@
+use crate::symbol_list_error::SymbolListError;
+use crate::version_script::glob_to_regex;
+use regex::Regex;
+
+/// A list of symbols to keep global, shared with the platform's linker configuration
+#[derive(Debug, Clone)]
+pub struct SymbolList {
+    regexes: Vec<Regex>,
+}
+
+impl SymbolList {
+    /// Parses a Darwin `-exported_symbols_list` file, with one symbol per line.
+    /// Names are matched as they appear in the object files (with the leading `_` of C symbols),
+    /// and can use the `*`, `?` and `[...]` wildcards.
+    pub fn parse_exported_symbols_list(list: &str) -> Result<Self, SymbolListError> {
+        let regexes = list
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(|name| {
+                let regex = glob_to_regex(name)
+                    .ok_or_else(|| SymbolListError::EmptyClass(name.to_owned()))?;
+                Ok(Regex::new(&format!("^{regex}$"))?)
+            })
+            .collect::<Result<Vec<_>, SymbolListError>>()?;
+        Self::new(regexes)
+    }
+
+    /// Parses the `EXPORTS` section of a Windows module-definition (`.def`) file.
+    /// Exports renamed with `exported=internal` keep the internal name, which is the one defined
+    /// in the objects, and exports forwarded to another DLL are ignored.
+    /// Like the linker, names also match the i386 decorations of their symbols in the objects:
+    /// `_name` (cdecl), `_name@N` (stdcall) and `@name@N` (fastcall).
+    pub fn parse_def_file(def: &str) -> Result<Self, SymbolListError> {
+        let mut regexes = Vec::new();
+        let mut in_exports = false;
+        for (line_idx, line) in def.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap_or_default();
+            if DEF_STATEMENTS.contains(&first.to_ascii_uppercase().as_str()) {
+                in_exports = first.eq_ignore_ascii_case("EXPORTS");
+                // Like the section name, the first export may be on the same line
+                let rest = line[first.len()..].trim();
+                if !in_exports || rest.is_empty() {
+                    continue;
+                }
+                if let Some(name) = parse_def_export(rest, line_idx + 1)? {
+                    regexes.push(name);
+                }
+                continue;
+            }
+            if in_exports {
+                if let Some(name) = parse_def_export(line, line_idx + 1)? {
+                    regexes.push(name);
+                }
+            }
+        }
+        Self::new(regexes)
+    }
+
+    fn new(regexes: Vec<Regex>) -> Result<Self, SymbolListError> {
+        if regexes.is_empty() {
+            return Err(SymbolListError::Empty);
+        }
+        Ok(Self { regexes })
+    }
+
+    /// Regexes matching the names of the symbols to keep
+    pub fn regexes(&self) -> &[Regex] {
+        &self.regexes
+    }
+
+    pub fn into_regexes(self) -> Vec<Regex> {
+        self.regexes
+    }
+}
+
+const DEF_STATEMENTS: &[&str] = &[
+    "NAME",
+    "LIBRARY",
+    "EXPORTS",
+    "HEAPSIZE",
+    "STACKSIZE",
+    "SECTIONS",
+    "VERSION",
+    "DESCRIPTION",
+];
+
+/// `entryname[=internalname] [@ordinal [NONAME]] [PRIVATE] [DATA]`
+fn parse_def_export(line: &str, line_number: usize) -> Result<Option<Regex>, SymbolListError> {
+    let entry = if let Some(quoted) = line.strip_prefix('"') {
+        let Some(end) = quoted.find('"') else {
+            return Err(SymbolListError::DefSyntax {
+                line: line_number,
+                reason: "unterminated quoted name".to_string(),
+            });
+        };
+        let rest = &quoted[end + 1..];
+        let internal = rest.trim_start().strip_prefix('=');
+        match internal {
+            Some(internal) => internal.split_whitespace().next().unwrap_or_default(),
+            None => &quoted[..end],
+        }
+    } else {
+        let first = line.split_whitespace().next().unwrap_or_default();
+        if first.starts_with('@') {
+            return Err(SymbolListError::DefSyntax {
+                line: line_number,
+                reason: format!("missing export name before ordinal `{first}`"),
+            });
+        }
+        match first.split_once('=') {
+            // `==` exports the symbol of another DLL under a different name
+            Some((_, internal)) if internal.starts_with('=') => return Ok(None),
+            Some((_, internal)) => internal,
+            None => first,
+        }
+    };
+    let name = entry.trim_matches('"');
+    // Forwarded exports (`name=other_dll.function`) aren't defined by the library
+    if name.is_empty() || name.contains('.') {
+        return Ok(None);
+    }
+    let pattern = format!("^[_@]?{}(?:@[0-9]+)?$", regex::escape(name));
+    Ok(Some(Regex::new(&pattern)?))
+}
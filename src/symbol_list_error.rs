@@ -0,0 +1,14 @@
+use thiserror::Error;
+
+/// Errors that happen while parsing a list of symbols to keep
+#[derive(Debug, Error)]
+pub enum SymbolListError {
+    #[error("module-definition file syntax error on line {line}: {reason}")]
+    DefSyntax { line: usize, reason: String },
+    #[error("symbol list doesn't contain any symbol")]
+    Empty,
+    #[error("empty character class in symbol pattern `{0}`")]
+    EmptyClass(String),
+    #[error("invalid symbol pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+}
@@ -0,0 +1,158 @@
+use crate::ArmergeKeepOrRemove;
+use object::SymbolKind;
+use regex::{Regex, RegexBuilder};
+
+/// What happens to a global symbol of the merged object
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SymbolAction {
+    /// The symbol stays global
+    Keep,
+    /// The symbol becomes local to the merged object
+    Localize,
+}
+
+/// An ordered list of rules deciding which global symbols of the merged object stay global.
+///
+/// The first rule whose regex matches the name of a symbol decides what happens to it, so
+/// `localize ^mylib_internal_` followed by `keep ^mylib_` keeps the `mylib_` API but hides its internals.
+/// Symbols that match no rule are localized if any rule keeps symbols, and kept otherwise,
+/// unless a default action is set with [`SymbolRules::with_default`].
+#[derive(Debug, Clone, Default)]
+pub struct SymbolRules {
+    rules: Vec<(SymbolAction, Regex)>,
+    default: Option<SymbolAction>,
+}
+
+impl SymbolRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The rules of `ArmergeKeepOrRemove`: keep only the matching symbols, or localize only the matching symbols
+    pub fn from_keep_or_remove(
+        keep_or_remove: ArmergeKeepOrRemove,
+        regexes: impl IntoIterator<Item = Regex>,
+    ) -> Self {
+        let (action, default) = match keep_or_remove {
+            ArmergeKeepOrRemove::KeepSymbols => (SymbolAction::Keep, SymbolAction::Localize),
+            ArmergeKeepOrRemove::RemoveSymbols => (SymbolAction::Localize, SymbolAction::Keep),
+        };
+        let mut rules = Self::new().with_default(default);
+        for regex in regexes {
+            rules.push(action, regex);
+        }
+        rules
+    }
+
+    /// Adds a rule that keeps the matching symbols global, after the existing rules
+    pub fn keep(mut self, regex: Regex) -> Self {
+        self.push(SymbolAction::Keep, regex);
+        self
+    }
+
+    /// Adds a rule that localizes the matching symbols, after the existing rules
+    pub fn localize(mut self, regex: Regex) -> Self {
+        self.push(SymbolAction::Localize, regex);
+        self
+    }
+
+    /// Sets what happens to the symbols that match no rule
+    pub fn with_default(mut self, action: SymbolAction) -> Self {
+        self.default = Some(action);
+        self
+    }
+
+    pub fn push(&mut self, action: SymbolAction, regex: Regex) {
+        self.rules.push((action, regex));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The rules, in the order they are evaluated
+    pub fn rules(&self) -> &[(SymbolAction, Regex)] {
+        &self.rules
+    }
+
+    /// What happens to the symbols that match no rule
+    pub fn default_action(&self) -> SymbolAction {
+        self.default.unwrap_or_else(|| {
+            if self
+                .rules
+                .iter()
+                .any(|(action, _)| *action == SymbolAction::Keep)
+            {
+                SymbolAction::Localize
+            } else {
+                SymbolAction::Keep
+            }
+        })
+    }
+
+    /// What happens to a global symbol with this name
+    pub fn action(&self, name: &str) -> SymbolAction {
+        match self.matching_rule(name) {
+            Some(idx) => self.rules[idx].0,
+            None => self.default_action(),
+        }
+    }
+
+    /// What the symbol filters do to a global symbol defined in the merged object.
+    /// Unlike [`SymbolRules::action`], symbols that match no rule are only localized by default if they are code or data.
+    pub(crate) fn filter_action(&self, name: &str, kind: SymbolKind) -> SymbolAction {
+        match self.matching_rule(name) {
+            Some(idx) => self.rules[idx].0,
+            None if self.default_action() == SymbolAction::Localize
+                && !matches!(
+                    kind,
+                    // ASM functions often end up unknown
+                    SymbolKind::Text | SymbolKind::Data | SymbolKind::Unknown
+                ) =>
+            {
+                SymbolAction::Keep
+            }
+            None => self.default_action(),
+        }
+    }
+
+    /// The index of the first rule matching the name
+    pub(crate) fn matching_rule(&self, name: &str) -> Option<usize> {
+        self.rules
+            .iter()
+            .position(|(_, regex)| regex.is_match(name))
+    }
+
+    /// Regexes matching the names of the symbols these rules keep, for the tools that only take keep regexes.
+    /// `names` are all the symbol names those tools may look up.
+    pub(crate) fn keep_regexes<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Vec<Regex> {
+        let keep_only = self.default_action() == SymbolAction::Localize
+            && self
+                .rules
+                .iter()
+                .all(|(action, _)| *action == SymbolAction::Keep);
+        if keep_only {
+            return self.rules.iter().map(|(_, regex)| regex.clone()).collect();
+        }
+
+        let kept = names
+            .into_iter()
+            .filter(|name| self.action(name) == SymbolAction::Keep);
+        exact_names_regex(kept).into_iter().collect()
+    }
+}
+
+/// A regex matching exactly these symbol names, or `None` if there are no names
+pub(crate) fn exact_names_regex<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<Regex> {
+    let mut names = names.into_iter().map(regex::escape).collect::<Vec<_>>();
+    if names.is_empty() {
+        return None;
+    }
+    names.sort_unstable();
+    names.dedup();
+    let regex = RegexBuilder::new(&format!("^(?:{})$", names.join("|")))
+        .size_limit(usize::MAX)
+        .build()
+        .expect("Failed to compile Regex");
+    Some(regex)
+}
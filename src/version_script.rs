@@ -0,0 +1,421 @@
+use crate::demangle;
+use crate::objects::ObjectTempDir;
+use crate::version_script_error::VersionScriptError;
+use crate::{ArchiveContents, ArmergeKeepOrRemove, MergeError};
+use regex::Regex;
+use tracing::info;
+
+/// A GNU ld version script (`{ global: foo_*; local: *; };`), used as the symbol visibility policy.
+///
+/// The symbols of all the version nodes are merged together, since static libraries have no
+/// symbol versions. When the script localizes everything else (`local: *;`), only the `global`
+/// symbols are kept, otherwise only the `local` symbols are localized. A script without `local`
+/// patterns keeps every symbol.
+#[derive(Debug, Clone)]
+pub struct VersionScript {
+    globals: Vec<Pattern>,
+    locals: Vec<Pattern>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Language {
+    C,
+    Cpp,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Scope {
+    Global,
+    Local,
+}
+
+/// A symbol name pattern. Quoted patterns are exact names, the rest are globs.
+/// Patterns in `extern "C++"` blocks match demangled names.
+#[derive(Debug, Clone)]
+struct Pattern {
+    language: Language,
+    text: String,
+    exact: bool,
+    /// The pattern translated to a regex, without anchors
+    regex: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Token {
+    OpenBrace,
+    CloseBrace,
+    Semicolon,
+    Colon,
+    Word(String),
+    Quoted(String),
+}
+
+impl VersionScript {
+    pub fn parse(script: &str) -> Result<Self, VersionScriptError> {
+        let tokens = tokenize(script)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            globals: Vec::new(),
+            locals: Vec::new(),
+        };
+        parser.parse_script()?;
+        if parser.locals.is_empty() {
+            info!("The version script has no local patterns, no symbol will be localized");
+        }
+        Ok(Self {
+            globals: parser.globals,
+            locals: parser.locals,
+        })
+    }
+
+    /// Translates the script into regexes matching the (mangled) names of the symbols in the objects.
+    /// C++ patterns are matched against the demangled names of the global symbols of the objects.
+    pub(crate) fn symbol_policy(
+        &self,
+        objects: &ObjectTempDir,
+        contents_type: ArchiveContents,
+    ) -> Result<(ArmergeKeepOrRemove, Vec<Regex>), MergeError> {
+        let localizes_everything = self
+            .locals
+            .iter()
+            .any(|p| p.language == Language::C && !p.exact && p.text == "*");
+        let (keep_or_remove, patterns) = if localizes_everything {
+            (ArmergeKeepOrRemove::KeepSymbols, &self.globals)
+        } else {
+            (ArmergeKeepOrRemove::RemoveSymbols, &self.locals)
+        };
+
+        // C symbols have a leading underscore on Mach-O
+        let prefix = if contents_type == ArchiveContents::MachO {
+            "_"
+        } else {
+            ""
+        };
+        let mut regexes = Vec::new();
+        let mut cpp_regexes = Vec::new();
+        for pattern in patterns {
+            let regex = &pattern.regex;
+            match pattern.language {
+                Language::C => regexes.push(format!("^{prefix}{regex}$")),
+                Language::Cpp => cpp_regexes.push(format!("^{regex}$")),
+            }
+        }
+        let cpp_regexes = cpp_regexes
+            .iter()
+            .map(|r| Regex::new(r))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| MergeError::InternalError(Box::new(e)))?;
+        if !cpp_regexes.is_empty() {
+            let cpp_symbols = demangle::matching_symbols(objects, &cpp_regexes, false)?;
+            info!(
+                "{} C++ symbols match the version script's extern \"C++\" patterns",
+                cpp_symbols.len()
+            );
+            regexes.extend(
+                cpp_symbols
+                    .iter()
+                    .map(|s| format!("^{}$", regex::escape(s))),
+            );
+        }
+
+        let regexes = regexes
+            .iter()
+            .map(|r| Regex::new(r))
+            .collect::<Result<_, _>>()
+            .map_err(|e| MergeError::InternalError(Box::new(e)))?;
+        Ok((keep_or_remove, regexes))
+    }
+}
+
+/// Version script patterns are shell globs, with `*`, `?` and `[...]` wildcards.
+/// Returns `None` for a glob with an empty `[]` class, which can't match anything.
+pub(crate) fn glob_to_regex(glob: &str) -> Option<String> {
+    let mut regex = String::with_capacity(glob.len() * 2);
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' if chars.clone().any(|c| c == ']') => {
+                regex.push('[');
+                if let Some('!' | '^') = chars.peek() {
+                    chars.next();
+                    regex.push('^');
+                }
+                if chars.peek() == Some(&']') {
+                    return None;
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    Some(regex)
+}
+
+fn tokenize(script: &str) -> Result<Vec<(usize, Token)>, VersionScriptError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = script.chars().peekable();
+    'next_char: while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = '\0';
+                loop {
+                    let Some(c) = chars.next() else {
+                        return Err(VersionScriptError::Syntax {
+                            line,
+                            reason: "unterminated comment".to_string(),
+                        });
+                    };
+                    if c == '\n' {
+                        line += 1;
+                    } else if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '{' => tokens.push((line, Token::OpenBrace)),
+            '}' => tokens.push((line, Token::CloseBrace)),
+            ';' => tokens.push((line, Token::Semicolon)),
+            ':' if chars.peek() != Some(&':') => tokens.push((line, Token::Colon)),
+            '"' => {
+                let start_line = line;
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            text.push(c);
+                        }
+                        None => {
+                            return Err(VersionScriptError::Syntax {
+                                line: start_line,
+                                reason: "unterminated string".to_string(),
+                            })
+                        }
+                    }
+                }
+                tokens.push((start_line, Token::Quoted(text)));
+            }
+            c => {
+                // Words end at a single colon, but C++ names contain `::`
+                let mut word = c.to_string();
+                if c == ':' {
+                    word.push(chars.next().unwrap_or(':'));
+                }
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '{' | '}' | ';' | '"' | '#') {
+                        break;
+                    }
+                    chars.next();
+                    if c == ':' {
+                        if chars.peek() != Some(&':') {
+                            tokens.push((line, Token::Word(word)));
+                            tokens.push((line, Token::Colon));
+                            continue 'next_char;
+                        }
+                        word.push(':');
+                        chars.next();
+                    }
+                    word.push(c);
+                }
+                tokens.push((line, Token::Word(word)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    pos: usize,
+    globals: Vec<Pattern>,
+    locals: Vec<Pattern>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(line, _)| *line)
+    }
+
+    fn next(&mut self) -> Result<Token, VersionScriptError> {
+        let token = self.tokens.get(self.pos).map(|(_, t)| t.clone());
+        self.pos += 1;
+        token.ok_or_else(|| VersionScriptError::Syntax {
+            line: self.line(),
+            reason: "unexpected end of script".to_string(),
+        })
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), VersionScriptError> {
+        let line = self.line();
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(VersionScriptError::Syntax {
+                line,
+                reason: format!("expected {expected:?}, found {token:?}"),
+            })
+        }
+    }
+
+    fn skip_semicolon(&mut self) {
+        if self.peek() == Some(&Token::Semicolon) {
+            self.pos += 1;
+        }
+    }
+
+    /// A script is either a single anonymous node, or a list of named nodes with their dependencies:
+    /// `VERS_1 { ... }; VERS_2 { ... } VERS_1;`
+    fn parse_script(&mut self) -> Result<(), VersionScriptError> {
+        while let Some(token) = self.peek() {
+            if let Token::Word(_) = token {
+                self.pos += 1;
+            }
+            self.expect(Token::OpenBrace)?;
+            let mut scope = Scope::Global;
+            self.parse_node(Language::C, &mut scope)?;
+            while let Some(Token::Word(_)) = self.peek() {
+                self.pos += 1;
+            }
+            self.expect(Token::Semicolon)?;
+        }
+        Ok(())
+    }
+
+    /// Parses the contents of a node or of an `extern` block, up to its closing brace
+    fn parse_node(
+        &mut self,
+        language: Language,
+        scope: &mut Scope,
+    ) -> Result<(), VersionScriptError> {
+        loop {
+            let line = self.line();
+            match self.next()? {
+                Token::CloseBrace => return Ok(()),
+                Token::Semicolon => {}
+                Token::OpenBrace => {
+                    return Err(VersionScriptError::Syntax {
+                        line,
+                        reason: "unexpected `{`".to_string(),
+                    })
+                }
+                Token::Quoted(text) => {
+                    self.push_pattern(*scope, language, text, true, line)?;
+                    self.skip_semicolon();
+                }
+                Token::Word(word) if word == "extern" => {
+                    let Token::Quoted(name) = self.next()? else {
+                        return Err(VersionScriptError::Syntax {
+                            line,
+                            reason: "expected a language name after `extern`".to_string(),
+                        });
+                    };
+                    let language = match name.as_str() {
+                        "C" => Language::C,
+                        "C++" => Language::Cpp,
+                        _ => {
+                            return Err(VersionScriptError::UnsupportedLanguage {
+                                line,
+                                language: name,
+                            })
+                        }
+                    };
+                    self.expect(Token::OpenBrace)?;
+                    self.parse_node(language, scope)?;
+                    self.skip_semicolon();
+                }
+                Token::Colon => {
+                    return Err(VersionScriptError::Syntax {
+                        line,
+                        reason: "unexpected `:`".to_string(),
+                    })
+                }
+                Token::Word(word) if self.peek() == Some(&Token::Colon) => {
+                    self.pos += 1;
+                    *scope = match word.as_str() {
+                        "global" => Scope::Global,
+                        "local" => Scope::Local,
+                        _ => {
+                            return Err(VersionScriptError::Syntax {
+                                line,
+                                reason: format!("unknown scope `{word}`"),
+                            })
+                        }
+                    };
+                }
+                Token::Word(word) => {
+                    self.push_pattern(*scope, language, word, false, line)?;
+                    self.skip_semicolon();
+                }
+            }
+        }
+    }
+
+    fn push_pattern(
+        &mut self,
+        scope: Scope,
+        language: Language,
+        text: String,
+        exact: bool,
+        line: usize,
+    ) -> Result<(), VersionScriptError> {
+        let invalid_pattern = |reason: String| VersionScriptError::InvalidPattern {
+            line,
+            pattern: text.clone(),
+            reason,
+        };
+        let regex = if exact {
+            regex::escape(&text)
+        } else {
+            glob_to_regex(&text)
+                .ok_or_else(|| invalid_pattern("empty character class".to_string()))?
+        };
+        Regex::new(&format!("^{regex}$")).map_err(|e| invalid_pattern(e.to_string()))?;
+        let pattern = Pattern {
+            language,
+            text,
+            exact,
+            regex,
+        };
+        match scope {
+            Scope::Global => self.globals.push(pattern),
+            Scope::Local => self.locals.push(pattern),
+        }
+        Ok(())
+    }
+}
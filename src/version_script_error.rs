@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+/// Errors that happen while parsing a GNU ld version script
+#[derive(Debug, Error)]
+pub enum VersionScriptError {
+    #[error("version script syntax error on line {line}: {reason}")]
+    Syntax { line: usize, reason: String },
+    #[error("unsupported language \"{language}\" in version script on line {line}")]
+    UnsupportedLanguage { line: usize, language: String },
+    #[error("invalid pattern `{pattern}` in version script on line {line}: {reason}")]
+    InvalidPattern {
+        line: usize,
+        pattern: String,
+        reason: String,
+    },
+}
@@ -0,0 +1,405 @@
+//! Checks the builtin ELF relocatable linker against `ld -r`, on objects built by the host compiler
+
+mod common;
+
+use common::{
+    armerge, armerge_log, armerge_ok, compile, has_tool, merged_object, write_archive_of,
+};
+use object::read::elf::{ElfFile64, FileHeader, SectionHeader};
+use object::{
+    elf, Endianness, Object, ObjectSection, ObjectSymbol, RelocationTarget, SectionIndex,
+    SymbolKind, SymbolSection,
+};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BUILTIN_LOG: &str = "with the builtin linker";
+
+// Keeps every symbol, so the outputs only differ by how they were linked
+const KEEP_ALL: &str = ".*";
+
+const LIB_A: &str = r#"
+static int counter = 3;
+static const char greeting[] = "hello";
+int shared_bss[16];
+extern int b_value(int);
+
+static int bump(int x) { return x + counter++; }
+int a_value(int x) { return bump(x) + b_value(x) + greeting[1]; }
+const char *a_name(void) { return greeting; }
+"#;
+
+const LIB_B: &str = r#"
+static int table[] = {1, 2, 3, 4};
+int a_value(int);
+
+int b_value(int x) { return table[x & 3]; }
+int b_calls_a(int x) { return a_value(x) * 2; }
+"#;
+
+const MAIN: &str = r#"
+int a_value(int);
+int b_calls_a(int);
+int main(void) { return a_value(1) == 'e' + 6 && b_calls_a(0) == 2 * ('e' + 5) ? 0 : 1; }
+"#;
+
+fn section_name(file: &object::File, index: Option<SectionIndex>) -> String {
+    index
+        .and_then(|index| file.section_by_index(index).ok())
+        .map(|section| section.name().unwrap().to_string())
+        .unwrap_or_default()
+}
+
+/// A sorted description of the named symbols of an object
+fn symbol_summary(data: &[u8]) -> Vec<String> {
+    let file = object::File::parse(data).unwrap();
+    let mut symbols = file
+        .symbols()
+        .filter(|sym| !matches!(sym.kind(), SymbolKind::Section | SymbolKind::File))
+        .filter(|sym| !sym.name().unwrap().is_empty())
+        .map(|sym| {
+            let section = match sym.section() {
+                SymbolSection::Section(index) => section_name(&file, Some(index)),
+                section => format!("{section:?}"),
+            };
+            format!(
+                "{} {:?} global={} weak={} {} {:#x} {}",
+                sym.name().unwrap(),
+                sym.kind(),
+                sym.is_global(),
+                sym.is_weak(),
+                section,
+                sym.address(),
+                sym.size()
+            )
+        })
+        .collect::<Vec<_>>();
+    symbols.sort();
+    symbols
+}
+
+/// A sorted description of the relocations of an object, with section symbols replaced by their section
+fn relocation_summary(data: &[u8]) -> Vec<String> {
+    let file = object::File::parse(data).unwrap();
+    let mut relocations = Vec::new();
+    for section in file.sections() {
+        let name = section.name().unwrap();
+        for (offset, reloc) in section.relocations() {
+            let target = match reloc.target() {
+                RelocationTarget::Symbol(index) => {
+                    let sym = file.symbol_by_index(index).unwrap();
+                    if sym.kind() == SymbolKind::Section {
+                        section_name(&file, sym.section_index())
+                    } else {
+                        sym.name().unwrap().to_string()
+                    }
+                }
+                target => format!("{target:?}"),
+            };
+            relocations.push(format!(
+                "{name} {offset:#x} {:?} {target} {}",
+                reloc.flags(),
+                reloc.addend()
+            ));
+        }
+    }
+    relocations.sort();
+    relocations
+}
+
+/// Merges the objects with the builtin linker and with `ld -r`, returning both merged objects
+fn merge_both_ways(dir: &Path, objects: &[PathBuf]) -> (Vec<u8>, Vec<u8>) {
+    let input = dir.join("input.a");
+    write_archive_of(&input, objects);
+    let builtin = dir.join("builtin.a");
+    let system = dir.join("system.a");
+
+    let builtin_log = armerge_ok(&[
+        "-k",
+        KEEP_ALL,
+        "-o",
+        builtin.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    assert!(builtin_log.contains(BUILTIN_LOG), "{builtin_log}");
+    let system_log = armerge_ok(&[
+        "--system-linker",
+        "-k",
+        KEEP_ALL,
+        "-o",
+        system.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    assert!(!system_log.contains(BUILTIN_LOG), "{system_log}");
+    (merged_object(&builtin), merged_object(&system))
+}
+
+/// Links the merged object into an executable with `main`, and checks that it runs successfully
+fn link_and_run(dir: &Path, merged: &[u8], main: &str, main_name: &str) {
+    let Some(main_object) = compile(dir, main_name, main, &[]) else {
+        return;
+    };
+    let merged_path = dir.join("merged.o");
+    std::fs::write(&merged_path, merged).unwrap();
+    let exe = dir.join("main");
+    let linker = if main_name.ends_with(".cpp") {
+        "c++"
+    } else {
+        "cc"
+    };
+    let status = Command::new(linker)
+        .arg("-o")
+        .arg(&exe)
+        .arg(&main_object)
+        .arg(&merged_path)
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed to link the merged object");
+    let status = Command::new(&exe).status().unwrap();
+    assert!(status.success(), "the linked executable returned {status}");
+}
+
+#[test]
+fn matches_ld_r_on_symbols_and_relocations() {
+    if !has_tool("ld") {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let flags = ["-O1", "-g", "-fcommon"];
+    let (Some(a), Some(b)) = (
+        compile(dir.path(), "a.c", LIB_A, &flags),
+        compile(dir.path(), "b.c", LIB_B, &flags),
+    ) else {
+        return;
+    };
+
+    let (builtin, system) = merge_both_ways(dir.path(), &[a, b]);
+    assert_eq!(symbol_summary(&builtin), symbol_summary(&system));
+    assert_eq!(relocation_summary(&builtin), relocation_summary(&system));
+    link_and_run(dir.path(), &builtin, MAIN, "main.c");
+}
+
+#[test]
+fn merges_common_symbols() {
+    if !has_tool("ld") {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let flags = ["-fcommon"];
+    let (Some(a), Some(b)) = (
+        compile(dir.path(), "a.c", "int common_value;", &flags),
+        compile(dir.path(), "b.c", "long common_value[4];", &flags),
+    ) else {
+        return;
+    };
+
+    let (builtin, system) = merge_both_ways(dir.path(), &[a, b]);
+    assert_eq!(symbol_summary(&builtin), symbol_summary(&system));
+    let file = object::File::parse(builtin.as_slice()).unwrap();
+    let common = file.symbol_by_name("common_value").unwrap();
+    assert!(common.is_common());
+    assert_eq!(common.size(), 32);
+}
+
+#[test]
+fn keeps_comdat_groups_linkable() {
+    if !has_tool("ld") {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let header = r#"
+template <typename T> struct Box { T value; T twice() const { return value * 2; } };
+inline int shared_inline(int x) { static int calls; calls++; return x + calls; }
+"#;
+    let a = format!(
+        "{header} int a_value(int x) {{ return Box<int>{{x}}.twice() + shared_inline(x); }}"
+    );
+    let b = format!(
+        "{header} int b_value(int x) {{ return Box<int>{{x}}.twice() - shared_inline(x); }}"
+    );
+    let main = r#"
+int a_value(int);
+int b_value(int);
+int main() { return a_value(1) == 4 && b_value(1) == -1 ? 0 : 1; }
+"#;
+    let (Some(a), Some(b)) = (
+        compile(dir.path(), "a.cpp", &a, &[]),
+        compile(dir.path(), "b.cpp", &b, &[]),
+    ) else {
+        return;
+    };
+
+    let (builtin, system) = merge_both_ways(dir.path(), &[a, b]);
+    // Duplicate groups are left to the final link, but the globals are the same as with `ld -r`
+    let global_names = |data: &[u8]| {
+        let file = object::File::parse(data).unwrap();
+        let mut names = file
+            .symbols()
+            .filter(|sym| sym.is_global())
+            .map(|sym| (sym.name().unwrap().to_string(), sym.is_undefined()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+    };
+    assert_eq!(global_names(&builtin), global_names(&system));
+
+    let groups = |data: &[u8]| {
+        let file = ElfFile64::<Endianness>::parse(data).unwrap();
+        let endian = file.endian();
+        file.elf_section_table()
+            .iter()
+            .filter(|section| section.sh_type(endian) == elf::SHT_GROUP)
+            .count()
+    };
+    assert_eq!(
+        groups(&builtin),
+        2 * groups(&system),
+        "both copies of each COMDAT group are kept"
+    );
+    link_and_run(dir.path(), &builtin, main, "main.cpp");
+}
+
+#[test]
+fn uncompresses_debug_sections() {
+    if !has_tool("ld") {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let (Some(a), Some(b)) = (
+        compile(dir.path(), "a.c", LIB_A, &["-g", "-gz=zlib"]),
+        compile(dir.path(), "b.c", LIB_B, &["-g", "-gz=zlib"]),
+    ) else {
+        return;
+    };
+    let a_data = std::fs::read(&a).unwrap();
+    let file = object::File::parse(a_data.as_slice()).unwrap();
+    let compressed = file.section_by_name(".debug_info").unwrap();
+    assert_ne!(
+        compressed.compressed_file_range().unwrap().format,
+        object::CompressionFormat::None,
+        "the compiler didn't compress the debug info"
+    );
+
+    let (builtin, system) = merge_both_ways(dir.path(), &[a, b]);
+    assert_eq!(relocation_summary(&builtin), relocation_summary(&system));
+    let file = ElfFile64::<Endianness>::parse(builtin.as_slice()).unwrap();
+    let debug_info = file.section_by_name(".debug_info").unwrap();
+    let flags = debug_info.elf_section_header().sh_flags(file.endian());
+    assert_eq!(flags & elf::SHF_COMPRESSED as u64, 0);
+    let system_file = object::File::parse(system.as_slice()).unwrap();
+    let system_debug_info = system_file.section_by_name(".debug_info").unwrap();
+    assert_eq!(
+        debug_info.uncompressed_data().unwrap(),
+        system_debug_info.uncompressed_data().unwrap()
+    );
+}
+
+#[test]
+fn uses_the_system_linker_for_rel_sections() {
+    let dir = tempfile::tempdir().unwrap();
+    let Some(a) = compile(dir.path(), "a.c", LIB_A, &[]) else {
+        return;
+    };
+
+    // Turn `.rela.text` into a REL section, the builtin linker must not try to read it
+    let mut data = std::fs::read(&a).unwrap();
+    let file = ElfFile64::<Endianness>::parse(data.as_slice()).unwrap();
+    let endian = file.endian();
+    let shoff = file.elf_header().e_shoff(endian) as usize;
+    let shentsize = file.elf_header().e_shentsize(endian) as usize;
+    let (index, _) = file
+        .elf_section_table()
+        .enumerate()
+        .find(|(_, section)| section.sh_type(endian) == elf::SHT_RELA)
+        .unwrap();
+    let sh_type_offset = shoff + index.0 * shentsize + 4;
+    data[sh_type_offset..sh_type_offset + 4].copy_from_slice(&elf::SHT_REL.to_le_bytes());
+    std::fs::write(&a, data).unwrap();
+
+    let input = dir.path().join("input.a");
+    write_archive_of(&input, &[a]);
+    let output = dir.path().join("output.a");
+    // The system linker may reject the mangled object, only the choice of linker matters here
+    let output = armerge(&[
+        "-k",
+        KEEP_ALL,
+        "-o",
+        output.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    let log = armerge_log(&output);
+    assert!(
+        log.contains("unsupported relocation section types"),
+        "{log}"
+    );
+    assert!(!log.contains(BUILTIN_LOG), "{log}");
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn does_not_claim_cet_when_an_input_lacks_it() {
+    if !has_tool("ld") {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let (Some(a), Some(b)) = (
+        compile(dir.path(), "a.c", LIB_A, &["-fcf-protection=full"]),
+        compile(dir.path(), "b.c", LIB_B, &["-fcf-protection=none"]),
+    ) else {
+        return;
+    };
+    let input = dir.path().join("input.a");
+    write_archive_of(&input, &[a]);
+    let cet_only = dir.path().join("cet_only.a");
+    armerge_ok(&[
+        "-k",
+        KEEP_ALL,
+        "-o",
+        cet_only.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    assert_ne!(
+        x86_features(&merged_object(&cet_only)) & elf::GNU_PROPERTY_X86_FEATURE_1_IBT,
+        0
+    );
+
+    write_archive_of(&input, &[dir.path().join("a.o"), b]);
+    let mixed = dir.path().join("mixed.a");
+    let log = armerge_ok(&[
+        "-k",
+        KEEP_ALL,
+        "-o",
+        mixed.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    assert!(log.contains("has GNU property notes"), "{log}");
+    assert_eq!(
+        x86_features(&merged_object(&mixed)) & elf::GNU_PROPERTY_X86_FEATURE_1_IBT,
+        0
+    );
+}
+
+/// The x86 feature bits that all the inputs of the object had, or 0 without a GNU property note
+#[cfg(target_arch = "x86_64")]
+fn x86_features(data: &[u8]) -> u32 {
+    let file = ElfFile64::<Endianness>::parse(data).unwrap();
+    let endian = file.endian();
+    let mut features = 0;
+    for section in file.elf_section_table().iter() {
+        let Some(mut notes) = section.notes(endian, data).unwrap() else {
+            continue;
+        };
+        while let Some(note) = notes.next().unwrap() {
+            let Some(mut properties) = note.gnu_properties(endian) else {
+                continue;
+            };
+            while let Some(property) = properties.next().unwrap() {
+                if property.pr_type() == elf::GNU_PROPERTY_X86_FEATURE_1_AND {
+                    features = property.data_u32(endian).unwrap();
+                }
+            }
+        }
+    }
+    features
+}
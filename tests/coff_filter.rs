@@ -0,0 +1,306 @@
+//! Checks the COFF merger on objects written with the `object` crate
+
+mod common;
+
+use common::{armerge_ok, merged_object, write_archive};
+use object::write::{self, Comdat, Relocation, SectionId, Symbol, SymbolId, SymbolSection};
+use object::{
+    pe, Architecture, BinaryFormat, ComdatKind, Endianness, FileKind, Object, ObjectSection,
+    ObjectSymbol, RelocationFlags, SectionFlags, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+};
+use std::path::Path;
+
+fn coff_object() -> write::Object<'static> {
+    write::Object::new(BinaryFormat::Coff, Architecture::X86_64, Endianness::Little)
+}
+
+fn add_function(object: &mut write::Object, section: SectionId, name: &str) -> SymbolId {
+    // `ret`, padded so that relocations have room
+    let offset = object.append_section_data(section, &[0xc3; 16], 16);
+    object.add_symbol(Symbol {
+        name: name.as_bytes().to_vec(),
+        value: offset,
+        size: 16,
+        kind: SymbolKind::Text,
+        scope: SymbolScope::Linkage,
+        weak: false,
+        section: SymbolSection::Section(section),
+        flags: SymbolFlags::None,
+    })
+}
+
+fn add_addr64(object: &mut write::Object, section: SectionId, offset: u64, symbol: SymbolId) {
+    object
+        .add_relocation(
+            section,
+            Relocation {
+                offset,
+                symbol,
+                addend: 0,
+                flags: RelocationFlags::Coff {
+                    typ: pe::IMAGE_REL_AMD64_ADDR64,
+                },
+            },
+        )
+        .unwrap();
+}
+
+fn add_directives(object: &mut write::Object, directives: &str) {
+    let section = object.add_section(Vec::new(), b".drectve".to_vec(), SectionKind::Linker);
+    object.append_section_data(section, directives.as_bytes(), 1);
+}
+
+/// An object with a COMDAT function `inline_fn` and its associative unwind data, called by `caller`
+fn object_with_comdat(caller: &str) -> Vec<u8> {
+    let mut object = coff_object();
+    let text = object.section_id(write::StandardSection::Text);
+    let caller = add_function(&mut object, text, caller);
+
+    // COMDAT sections are defined by their section symbols, which come before the COMDAT symbol
+    let comdat_text =
+        object.add_section(Vec::new(), b".text$inline_fn".to_vec(), SectionKind::Text);
+    object.section_symbol(comdat_text);
+    let inline_fn = add_function(&mut object, comdat_text, "inline_fn");
+    let comdat_data = object.add_section(
+        Vec::new(),
+        b".xdata$inline_fn".to_vec(),
+        SectionKind::ReadOnlyData,
+    );
+    object.section_symbol(comdat_data);
+    object.append_section_data(comdat_data, &[1; 8], 8);
+    object.add_comdat(Comdat {
+        kind: ComdatKind::Any,
+        symbol: inline_fn,
+        sections: vec![comdat_text, comdat_data],
+    });
+
+    let caller_offset = object.symbol(caller).value;
+    add_addr64(&mut object, text, caller_offset, inline_fn);
+    object.write().unwrap()
+}
+
+/// Merges COFF objects, keeping the globals that match `keep`, and returns the merged object
+fn merge(dir: &Path, objects: &[(&str, Vec<u8>)], keep: &str) -> Vec<u8> {
+    let members = objects
+        .iter()
+        .map(|(name, data)| (*name, data.as_slice()))
+        .collect::<Vec<_>>();
+    let input = dir.join("input.lib");
+    write_archive(&input, &members);
+    let output = dir.join("output.lib");
+    armerge_ok(&[
+        "-k",
+        keep,
+        "-o",
+        output.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    merged_object(&output)
+}
+
+fn coff_characteristics(section: &object::Section) -> u32 {
+    match section.flags() {
+        SectionFlags::Coff { characteristics } => characteristics,
+        flags => panic!("not a COFF section: {flags:?}"),
+    }
+}
+
+#[test]
+fn drops_exports_of_localized_symbols() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut object = coff_object();
+    let text = object.section_id(write::StandardSection::Text);
+    add_function(&mut object, text, "keep_api");
+    add_function(&mut object, text, "internal_helper");
+    add_function(&mut object, text, "renamed_impl");
+    add_directives(
+        &mut object,
+        " /EXPORT:keep_api /EXPORT:internal_helper,DATA \"/EXPORT:public_name=renamed_impl\" /DEFAULTLIB:\"LIBCMT\"",
+    );
+
+    let merged = merge(
+        dir.path(),
+        &[("api.obj", object.write().unwrap())],
+        "^keep_",
+    );
+    let file = object::File::parse(merged.as_slice()).unwrap();
+    let directives = file.section_by_name(".drectve").unwrap();
+    let directives = String::from_utf8(directives.data().unwrap().to_vec()).unwrap();
+    assert_eq!(directives, " /EXPORT:keep_api /DEFAULTLIB:\"LIBCMT\"");
+    assert!(file.symbol_by_name("keep_api").unwrap().is_global());
+    assert!(file.symbol_by_name("internal_helper").unwrap().is_local());
+    assert!(file.symbol_by_name("renamed_impl").unwrap().is_local());
+}
+
+#[test]
+fn keeps_one_copy_of_each_comdat() {
+    let dir = tempfile::tempdir().unwrap();
+    let objects = [
+        ("a.obj", object_with_comdat("keep_a")),
+        ("b.obj", object_with_comdat("keep_b")),
+    ];
+    let merged = merge(dir.path(), &objects, "^keep_|^inline_fn$");
+    let file = object::File::parse(merged.as_slice()).unwrap();
+
+    // The associative section is discarded along with the losing copy
+    let count = |name: &str| {
+        file.sections()
+            .filter(|section| section.name() == Ok(name))
+            .count()
+    };
+    assert_eq!(count(".text$inline_fn"), 1);
+    assert_eq!(count(".xdata$inline_fn"), 1);
+    let comdat_text = file.section_by_name(".text$inline_fn").unwrap();
+    assert_ne!(
+        coff_characteristics(&comdat_text) & pe::IMAGE_SCN_LNK_COMDAT,
+        0
+    );
+
+    // Both callers refer to the winning copy
+    let inline_fn = file.symbol_by_name("inline_fn").unwrap();
+    assert_eq!(inline_fn.section_index(), Some(comdat_text.index()));
+    let targets = file
+        .sections()
+        .filter(|section| section.name() == Ok(".text"))
+        .flat_map(|section| section.relocations())
+        .map(|(_, reloc)| reloc.target())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        targets,
+        vec![object::RelocationTarget::Symbol(inline_fn.index()); 2]
+    );
+}
+
+#[test]
+fn demotes_localized_comdats() {
+    let dir = tempfile::tempdir().unwrap();
+    let objects = [
+        ("a.obj", object_with_comdat("keep_a")),
+        ("b.obj", object_with_comdat("keep_b")),
+    ];
+    let merged = merge(dir.path(), &objects, "^keep_");
+    let file = object::File::parse(merged.as_slice()).unwrap();
+
+    assert!(file.symbol_by_name("inline_fn").unwrap().is_local());
+    for name in [".text$inline_fn", ".xdata$inline_fn"] {
+        let section = file.section_by_name(name).unwrap();
+        assert_eq!(
+            coff_characteristics(&section) & pe::IMAGE_SCN_LNK_COMDAT,
+            0,
+            "{name} is still a COMDAT"
+        );
+    }
+}
+
+#[test]
+fn resolves_weak_externals() {
+    let dir = tempfile::tempdir().unwrap();
+    let weak_object = |caller: &str| {
+        let mut object = coff_object();
+        let text = object.section_id(write::StandardSection::Text);
+        add_function(&mut object, text, caller);
+        let weak = add_function(&mut object, text, "weak_fn");
+        object.symbol_mut(weak).weak = true;
+        object.write().unwrap()
+    };
+    let mut strong = coff_object();
+    let text = strong.section_id(write::StandardSection::Text);
+    add_function(&mut strong, text, "weak_fn");
+    add_function(&mut strong, text, "keep_strong");
+
+    // A strong definition replaces the weak external
+    let merged = merge(
+        dir.path(),
+        &[
+            ("weak.obj", weak_object("keep_weak")),
+            ("strong.obj", strong.write().unwrap()),
+        ],
+        "^keep_|^weak_fn$",
+    );
+    let file = object::File::parse(merged.as_slice()).unwrap();
+    let weak_fn = file
+        .symbols()
+        .filter(|sym| sym.name() == Ok("weak_fn"))
+        .collect::<Vec<_>>();
+    assert_eq!(weak_fn.len(), 1);
+    assert!(weak_fn[0].is_definition());
+    assert!(!weak_fn[0].is_weak());
+
+    // Without one, it stays a weak external with its default definition
+    let merged = merge(
+        dir.path(),
+        &[("weak.obj", weak_object("keep_weak"))],
+        "^keep_|^weak_fn$",
+    );
+    let file = object::File::parse(merged.as_slice()).unwrap();
+    let weak_fn = file.symbol_by_name("weak_fn").unwrap();
+    assert!(weak_fn.is_weak());
+    assert!(file
+        .symbols()
+        .any(|sym| sym.name().unwrap().starts_with(".weak.weak_fn.") && sym.is_definition()));
+}
+
+#[test]
+fn keeps_relocation_counts_that_overflow() {
+    const COUNT: usize = 70_000;
+    let dir = tempfile::tempdir().unwrap();
+    let mut object = coff_object();
+    let text = object.section_id(write::StandardSection::Text);
+    add_function(&mut object, text, "keep_target");
+    let data = object.section_id(write::StandardSection::Data);
+    object.append_section_data(data, &vec![0; COUNT * 8], 8);
+    let target = object.symbol_id(b"keep_target").unwrap();
+    for i in 0..COUNT {
+        add_addr64(&mut object, data, i as u64 * 8, target);
+    }
+
+    let merged = merge(
+        dir.path(),
+        &[("big.obj", object.write().unwrap())],
+        "^keep_",
+    );
+    let file = object::File::parse(merged.as_slice()).unwrap();
+    let data = file.section_by_name(".data").unwrap();
+    assert_ne!(
+        coff_characteristics(&data) & pe::IMAGE_SCN_LNK_NRELOC_OVFL,
+        0
+    );
+    assert_eq!(data.relocations().count(), COUNT);
+}
+
+#[test]
+fn switches_to_bigobj_when_sections_overflow() {
+    const SECTIONS: usize = 33_000;
+    let dir = tempfile::tempdir().unwrap();
+    let many_sections = |name: &str| {
+        let mut object = coff_object();
+        for i in 0..SECTIONS {
+            let section = object.add_section(
+                Vec::new(),
+                format!(".text${name}{i}").into_bytes(),
+                SectionKind::Text,
+            );
+            add_function(&mut object, section, &format!("keep_{name}{i}"));
+        }
+        object.write().unwrap()
+    };
+
+    let merged = merge(
+        dir.path(),
+        &[("a.obj", many_sections("a")), ("b.obj", many_sections("b"))],
+        "^keep_",
+    );
+    assert_eq!(
+        FileKind::parse(merged.as_slice()).unwrap(),
+        FileKind::CoffBig
+    );
+    let file = object::File::parse(merged.as_slice()).unwrap();
+    assert_eq!(file.sections().count(), 2 * SECTIONS);
+    let last = file
+        .symbol_by_name(&format!("keep_b{}", SECTIONS - 1))
+        .unwrap();
+    let section = file
+        .section_by_index(last.section_index().unwrap())
+        .unwrap();
+    assert_eq!(section.name().unwrap(), format!(".text$b{}", SECTIONS - 1));
+}
@@ -0,0 +1,120 @@
+// Each test binary only uses some of the helpers
+#![allow(dead_code)]
+
+use object::read::archive::ArchiveFile;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Compiles a C or C++ source to an object with the host compiler, or returns `None` without one
+pub fn compile(dir: &Path, name: &str, source: &str, flags: &[&str]) -> Option<PathBuf> {
+    let (compiler, extension) = if name.ends_with(".cpp") {
+        ("c++", "cpp")
+    } else {
+        ("cc", "c")
+    };
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let source_path = dir.join(format!("{stem}.{extension}"));
+    let object_path = dir.join(format!("{stem}.o"));
+    std::fs::write(&source_path, source).unwrap();
+    let status = Command::new(compiler)
+        .arg("-c")
+        .args(flags)
+        .arg("-o")
+        .arg(&object_path)
+        .arg(&source_path)
+        .status();
+    match status {
+        Ok(status) if status.success() => Some(object_path),
+        Ok(status) => panic!("{compiler} failed to compile {name}: {status}"),
+        Err(e) => {
+            eprintln!("skipping test, can't run {compiler}: {e}");
+            None
+        }
+    }
+}
+
+/// Whether an external tool can be launched
+pub fn has_tool(tool: &str) -> bool {
+    let found = Command::new(tool).arg("--version").output().is_ok();
+    if !found {
+        eprintln!("skipping test, {tool} is not installed");
+    }
+    found
+}
+
+/// Writes a static library with these members
+pub fn write_archive(path: &Path, members: &[(&str, &[u8])]) {
+    let mut builder = ar::Builder::new(std::fs::File::create(path).unwrap());
+    for (name, data) in members {
+        let header = ar::Header::new(name.as_bytes().to_vec(), data.len() as u64);
+        builder.append(&header, *data).unwrap();
+    }
+}
+
+/// Writes a static library with these object files
+pub fn write_archive_of(path: &Path, objects: &[PathBuf]) {
+    let contents = objects
+        .iter()
+        .map(|path| std::fs::read(path).unwrap())
+        .collect::<Vec<_>>();
+    let names = objects
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap())
+        .collect::<Vec<_>>();
+    let members = names
+        .iter()
+        .zip(&contents)
+        .map(|(name, data)| (*name, data.as_slice()))
+        .collect::<Vec<_>>();
+    write_archive(path, &members);
+}
+
+/// Runs the armerge binary, with `RUST_LOG=info` so tests can check which path was taken
+pub fn armerge(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_armerge"))
+        .args(args)
+        .env("RUST_LOG", "info")
+        .env_remove("LD")
+        .env_remove("ARMERGE_LDFLAGS")
+        .output()
+        .unwrap()
+}
+
+/// The logs and errors that armerge printed
+pub fn armerge_log(output: &Output) -> String {
+    [
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr),
+    ]
+    .concat()
+}
+
+/// Runs the armerge binary and returns its logs, or panics with them if it fails
+pub fn armerge_ok(args: &[&str]) -> String {
+    let output = armerge(args);
+    let log = armerge_log(&output);
+    assert!(output.status.success(), "armerge {args:?} failed:\n{log}");
+    log
+}
+
+/// The members of a static library that are object files, skipping the symbol tables
+pub fn archive_objects(path: &Path) -> Vec<(String, Vec<u8>)> {
+    let data = std::fs::read(path).unwrap();
+    let archive = ArchiveFile::parse(data.as_slice()).unwrap();
+    archive
+        .members()
+        .map(|member| member.unwrap())
+        .map(|member| {
+            let name = String::from_utf8_lossy(member.name()).into_owned();
+            (name, member.data(data.as_slice()).unwrap().to_vec())
+        })
+        .filter(|(_, data)| object::File::parse(data.as_slice()).is_ok())
+        .collect()
+}
+
+/// The single object of a merged static library
+pub fn merged_object(path: &Path) -> Vec<u8> {
+    let mut objects = archive_objects(path);
+    assert_eq!(objects.len(), 1, "expected a single merged object");
+    objects.remove(0).1
+}
@@ -0,0 +1,49 @@
+//! Checks the symbol renaming of the merged object
+
+mod common;
+
+use common::{armerge_ok, compile, merged_object, write_archive_of};
+use object::{Object, ObjectSymbol};
+
+#[test]
+fn prefix_skips_runtime_symbols() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = r#"
+int _Unwind_Backtrace_stub(void) { return 1; }
+int __gcc_personality_v0(void) { return 2; }
+int api_call(void) { return _Unwind_Backtrace_stub() + __gcc_personality_v0(); }
+"#;
+    let Some(object) = compile(dir.path(), "api.c", source, &[]) else {
+        return;
+    };
+    let input = dir.path().join("input.a");
+    write_archive_of(&input, &[object]);
+    let output = dir.path().join("output.a");
+    armerge_ok(&[
+        "-k",
+        "^api_",
+        "--prefix-symbols",
+        "ns_",
+        "-o",
+        output.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+
+    let merged = merged_object(&output);
+    let file = object::File::parse(merged.as_slice()).unwrap();
+    let globals = file
+        .symbols()
+        .filter(|sym| sym.is_global() && sym.is_definition())
+        .map(|sym| sym.name().unwrap().to_string())
+        .collect::<Vec<_>>();
+    for name in [
+        "ns_api_call",
+        "_Unwind_Backtrace_stub",
+        "__gcc_personality_v0",
+    ] {
+        assert!(
+            globals.iter().any(|global| global == name),
+            "{name} in {globals:?}"
+        );
+    }
+}
@@ -0,0 +1,40 @@
+//! Checks how the members of thin input archives are found
+
+mod common;
+
+use armerge::{ArMerger, InputLibrary};
+use common::{compile, has_tool};
+use std::fs::File;
+use std::process::Command;
+
+#[test]
+fn thin_members_need_a_base_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let Some(object) = compile(dir.path(), "api.c", "int api_call(void) { return 1; }", &[]) else {
+        return;
+    };
+    if !has_tool("ar") {
+        return;
+    }
+    let input = dir.path().join("thin.a");
+    let status = Command::new("ar")
+        .current_dir(dir.path())
+        .args([
+            "rcT",
+            "thin.a",
+            object.file_name().unwrap().to_str().unwrap(),
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = dir.path().join("output.a");
+
+    // The members are not looked up relative to the current directory
+    let library = InputLibrary::new("thin.a", File::open(&input).unwrap());
+    let error = ArMerger::new([library], &output).unwrap_err();
+    assert!(error.to_string().contains("with_base_dir"), "{error}");
+
+    let library =
+        InputLibrary::new("thin.a", File::open(&input).unwrap()).with_base_dir(dir.path());
+    ArMerger::new([library], &output).unwrap();
+}